@@ -1,5 +1,7 @@
 import struct
 import logging
+import hashlib
+import zlib
 import traceback
 import numpy
 from enum import Enum
@@ -12,13 +14,21 @@ from artiq import __version__ as software_version
 
 
 logger = logging.getLogger(__name__)
+kernel_logger = logging.getLogger("kernel")
 
 
 class Request(Enum):
     SystemInfo = 3
 
     LoadKernel = 5
+    LoadKernelByHash = 9
+    LoadAndCacheKernel = 10
+    LoadCompressedKernel = 11
+    StageKernel = 13
     RunKernel = 6
+    AbortKernel = 12
+    GetRunStats = 14
+    ForwardKernelLog = 15
 
     RPCReply = 7
     RPCException = 8
@@ -26,20 +36,36 @@ class Request(Enum):
 
 class Reply(Enum):
     SystemInfo = 2
+    SystemInfoWithCapabilities = 21
 
     LoadCompleted = 5
     LoadFailed = 6
+    KernelNotCached = 16
+    StagedKernelLoaded = 18
 
     KernelFinished = 7
     KernelStartupFailed = 8
     KernelException = 9
+    KernelAborted = 17
+    RunStats = 19
 
     RPCRequest = 10
+    KernelLog = 20
 
     WatchdogExpired = 14
     ClockFailure = 15
 
 
+# Bits of the capability flags sent with the system information
+CAPABILITY_FLAGS = ["rtio_dma", "drtio", "rtio_analyzer", "moninj", "grabber",
+                    "kernel_cache", "kernel_deflate", "kernel_abort",
+                    "kernel_staging", "run_stats", "kernel_log",
+                    "converter_spi"]
+# Features that may be present in firmware that does not report capabilities
+LEGACY_CAPABILITIES = ["rtio_dma", "drtio", "rtio_analyzer", "moninj",
+                       "grabber"]
+
+
 class UnsupportedDevice(Exception):
     pass
 
@@ -63,12 +89,24 @@ class CommKernelDummy:
     def run(self):
         pass
 
+    def abort(self):
+        pass
+
+    def stage(self, kernel_library):
+        pass
+
+    def get_run_stats(self):
+        return None
+
     def serve(self, embedding_map, symbolizer, demangler):
         pass
 
     def check_system_info(self):
         pass
 
+    def check_capability(self, capability, purpose):
+        pass
+
 
 class CommKernel:
     warned_of_mismatch = False
@@ -77,18 +115,36 @@ class CommKernel:
         self._read_type = None
         self.host = host
         self.port = port
+        self.protocol_version = None
+        self.capabilities = None
+        self._abort_requested = False
+        self._staged = None
+        self._staged_pending = False
+        self.staged_load_time = None
+        self.forward_kernel_log = False
 
     def open(self, **kwargs):
         if hasattr(self, "socket"):
             return
         self.socket = initialize_connection(self.host, self.port, **kwargs)
         self.socket.sendall(b"ARTIQ coredev\n")
+        self._configure_session()
+
+    def _configure_session(self):
+        # Session options are lost when the connection is closed.
+        if self.forward_kernel_log and self.capabilities is not None \
+                and self.capabilities["kernel_log"]:
+            self._write_header(Request.ForwardKernelLog)
+            self._write_bool(True)
 
     def close(self):
         if not hasattr(self, "socket"):
             return
         self.socket.close()
         del self.socket
+        self._abort_requested = False
+        self._staged = None
+        self._staged_pending = False
         logger.debug("disconnected")
 
     def read(self, length):
@@ -214,7 +270,8 @@ class CommKernel:
         self._write_empty(Request.SystemInfo)
 
         self._read_header()
-        self._read_expect(Reply.SystemInfo)
+        if self._read_type != Reply.SystemInfo:
+            self._read_expect(Reply.SystemInfoWithCapabilities)
         runtime_id = self._read_chunk(4)
         if runtime_id != b"AROR":
             raise UnsupportedDevice("Unsupported runtime ID: {}"
@@ -231,11 +288,96 @@ class CommKernel:
         if not finished_cleanly:
             logger.warning("Previous kernel did not cleanly finish")
 
-    def load(self, kernel_library):
-        self._write_header(Request.LoadKernel)
-        self._write_bytes(kernel_library)
+        if self._read_type == Reply.SystemInfo:
+            logger.debug("firmware does not report its capabilities")
+            # Version 0 stands for the protocol before it was versioned.
+            self.protocol_version = 0
+            self.capabilities = None
+            if self.forward_kernel_log:
+                logger.warning("Core device firmware cannot forward kernel logs")
+            return
 
+        self.protocol_version = self._read_int8()
+        flags = self._read_int32()
+        capabilities = {name: bool(flags & (1 << bit))
+                        for bit, name in enumerate(CAPABILITY_FLAGS)}
+        capabilities["i2c_buses"] = self._read_int8()
+        capabilities["drtio_links"] = self._read_int8()
+        self.capabilities = capabilities
+        logger.debug("protocol version %d, capabilities %s",
+                     self.protocol_version, capabilities)
+
+        if self.forward_kernel_log and not capabilities["kernel_log"]:
+            logger.warning("Core device firmware cannot forward kernel logs")
+        self._configure_session()
+
+    def check_capability(self, capability, purpose):
+        """Raise :class:`UnsupportedDevice` if the core device firmware
+        lacks ``capability`` (one of the keys of :attr:`capabilities`), which
+        is needed for ``purpose``."""
+        if self.protocol_version is None:
+            self.check_system_info()
+        if self.capabilities is None:
+            # The firmware predates capability reporting: it cannot have any
+            # of the newer session features, and whether it was built with
+            # the others cannot be told.
+            if capability in LEGACY_CAPABILITIES:
+                return
+        elif self.capabilities[capability]:
+            return
+        raise UnsupportedDevice("The core device firmware does not support "
+                                "{}, which is needed for {}"
+                                .format(capability, purpose))
+
+    def _upload(self, kernel_library, digest):
+        if self.capabilities is not None and self.capabilities["kernel_deflate"]:
+            compressor = zlib.compressobj(9, zlib.DEFLATED, -15)
+            compressed = compressor.compress(kernel_library) + compressor.flush()
+            logger.debug("uploading kernel compressed from %d to %d bytes",
+                         len(kernel_library), len(compressed))
+            self._write_header(Request.LoadCompressedKernel)
+            self._write_chunk(digest)
+            self._write_int32(len(kernel_library))
+            self._write_bytes(compressed)
+        elif self.capabilities is not None and self.capabilities["kernel_cache"]:
+            self._write_header(Request.LoadAndCacheKernel)
+            self._write_chunk(digest)
+            self._write_bytes(kernel_library)
+        else:
+            self._write_header(Request.LoadKernel)
+            self._write_bytes(kernel_library)
         self._read_header()
+
+    def load(self, kernel_library):
+        digest = hashlib.sha256(kernel_library).digest()
+        self._read_staged_reply()
+        if self._staged is not None:
+            staged_digest, error = self._staged
+            self._staged = None
+            if staged_digest == digest:
+                if error is not None:
+                    raise LoadError(error)
+                logger.debug("kernel already loaded from staging")
+                return
+            elif error is None:
+                # Unload the staged kernel.
+                self._write_empty(Request.AbortKernel)
+                self._read_header()
+                self._read_expect(Reply.KernelAborted)
+
+        if self.capabilities is not None and self.capabilities["kernel_cache"]:
+            # Only upload the library if the core device has not kept it
+            # from an earlier run.
+            self._write_header(Request.LoadKernelByHash)
+            self._write_chunk(digest)
+
+            self._read_header()
+            if self._read_type == Reply.KernelNotCached:
+                logger.debug("kernel not cached, uploading")
+                self._upload(kernel_library, digest)
+        else:
+            self._upload(kernel_library, digest)
+
         if self._read_type == Reply.LoadFailed:
             raise LoadError(self._read_string())
         else:
@@ -245,6 +387,81 @@ class CommKernel:
         self._write_empty(Request.RunKernel)
         logger.debug("running kernel")
 
+    def abort(self):
+        """Ask the core device to stop the running kernel while keeping the
+        connection open.
+
+        This is meant to be called from an RPC; :meth:`serve` then raises
+        :class:`artiq.coredevice.exceptions.KernelAborted` once the core
+        device has stopped the kernel, and the next kernel can be loaded
+        as usual."""
+        if self._abort_requested:
+            return
+        self.check_capability("kernel_abort", "aborting kernels")
+        self._write_empty(Request.AbortKernel)
+        self._abort_requested = True
+        logger.debug("aborting kernel")
+
+    def stage(self, kernel_library):
+        """Upload the next kernel while the current one is running.
+
+        The core device loads it as soon as the running kernel ends, and
+        the following :meth:`load` of the same library returns at once.
+        Only one kernel can be staged per run. Like :meth:`abort`, this is
+        meant to be called from an RPC. The time the core device took to load
+        the kernel is then available in :attr:`staged_load_time`, in
+        seconds."""
+        self.check_capability("kernel_staging", "staging kernels")
+        if self._staged_pending:
+            raise RuntimeError("A kernel is already staged")
+        if self._abort_requested:
+            return
+        self._write_header(Request.StageKernel)
+        self._write_bytes(kernel_library)
+        # The core device answers once the kernel is loaded.
+        self._staged = (hashlib.sha256(kernel_library).digest(), None)
+        self._staged_pending = True
+        logger.debug("staging kernel")
+
+    def _read_staged_reply(self):
+        if self._staged is None or not self._staged_pending:
+            return
+        self._staged_pending = False
+        self._read_header()
+        if self._read_type == Reply.LoadFailed:
+            self._staged = (self._staged[0], self._read_string())
+        else:
+            self._read_expect(Reply.StagedKernelLoaded)
+            self.staged_load_time = self._read_int64()*1e-6
+            logger.debug("staged kernel loaded in %.1f ms",
+                         self.staged_load_time*1e3)
+
+    def get_run_stats(self):
+        """Return statistics about the last kernel run on this connection
+        as a dictionary, or ``None`` if no kernel has run yet.
+
+        Times are in seconds. ``rpc_queue_peak`` is the largest number of
+        asynchronous RPCs that the core device saw waiting to be sent."""
+        self.check_capability("run_stats", "kernel run statistics")
+        self._write_empty(Request.GetRunStats)
+        self._read_header()
+        self._read_expect(Reply.RunStats)
+        if not self._read_bool():
+            return None
+        return {
+            "load_time": self._read_int64()*1e-6,
+            "run_time": self._read_int64()*1e-6,
+            "sync_rpcs": self._read_int32(),
+            "async_rpcs": self._read_int32(),
+            "rpc_bytes_sent": self._read_int64(),
+            "rpc_bytes_received": self._read_int64(),
+            "dma_recorded": self._read_int32(),
+            "dma_played": self._read_int32(),
+            "cache_gets": self._read_int32(),
+            "cache_puts": self._read_int32(),
+            "rpc_queue_peak": self._read_int32()
+        }
+
     _rpc_sentinel = object()
 
     # See session.c:{send,receive}_rpc_value and llvm_ir_generator.py:_rpc_tag.
@@ -415,6 +632,10 @@ class CommKernel:
         logger.debug("rpc service: [%d]%r%s %r %r -> %s", service_id, service,
                      (" (async)" if is_async else ""), args, kwargs, return_tags)
 
+        if self._abort_requested:
+            # Sent by the kernel before it was stopped.
+            return
+
         if is_async:
             service(*args, **kwargs)
             return
@@ -422,6 +643,9 @@ class CommKernel:
         try:
             result = service(*args, **kwargs)
             logger.debug("rpc service: %d %r %r = %r", service_id, args, kwargs, result)
+            if self._abort_requested:
+                # The kernel is no longer waiting for the result.
+                return
 
             self._write_header(Request.RPCReply)
             self._write_bytes(return_tags)
@@ -430,6 +654,8 @@ class CommKernel:
             raise
         except Exception as exn:
             logger.debug("rpc service: %d %r %r ! %r", service_id, args, kwargs, exn)
+            if self._abort_requested:
+                return
 
             self._write_header(Request.RPCException)
 
@@ -496,17 +722,48 @@ class CommKernel:
         python_exn.artiq_core_exception = core_exn
         raise python_exn
 
+    def _serve_kernel_log(self):
+        run_id = self._read_int32()
+        message = self._read_string()
+        kernel_logger.info("%s", message, extra={"run_id": run_id})
+
+    def _read_abort_reply(self):
+        if self._abort_requested:
+            # The kernel ended before the request reached the core device,
+            # which still acknowledges it and unloads any staged kernel.
+            self._read_header()
+            self._read_expect(Reply.KernelAborted)
+            self._abort_requested = False
+            self._staged = None
+            self._staged_pending = False
+
+    def _read_end_replies(self):
+        self._read_staged_reply()
+        self._read_abort_reply()
+
     def serve(self, embedding_map, symbolizer, demangler):
         while True:
             self._read_header()
             if self._read_type == Reply.RPCRequest:
                 self._serve_rpc(embedding_map)
+            elif self._read_type == Reply.KernelLog:
+                self._serve_kernel_log()
             elif self._read_type == Reply.KernelException:
-                self._serve_exception(embedding_map, symbolizer, demangler)
+                try:
+                    self._serve_exception(embedding_map, symbolizer, demangler)
+                finally:
+                    self._read_end_replies()
             elif self._read_type == Reply.WatchdogExpired:
                 raise exceptions.WatchdogExpired
             elif self._read_type == Reply.ClockFailure:
                 raise exceptions.ClockFailure
+            elif self._read_type == Reply.KernelAborted:
+                # The core device drops any staged kernel without replying.
+                self._abort_requested = False
+                self._staged = None
+                self._staged_pending = False
+                raise exceptions.KernelAborted
             else:
                 self._read_expect(Reply.KernelFinished)
+                self._read_end_replies()
                 return
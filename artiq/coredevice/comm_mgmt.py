@@ -1,4 +1,4 @@
-from enum import Enum
+from enum import Enum, IntFlag
 import logging
 import socket
 import struct
@@ -24,11 +24,21 @@ class Request(Enum):
     StartProfiler = 9
     StopProfiler = 10
     GetProfile = 11
+    StreamProfile = 20
 
     Hotswap = 4
     Reboot = 5
 
     DebugAllocator = 8
+    CheckHeap = 19
+
+    GetClockMapping = 16
+    GetEthernetStatistics = 17
+
+    GetThreads = 18
+
+    ConfigurePerfCounters = 21
+    ReadPerfCounters = 22
 
 
 class Reply(Enum):
@@ -44,6 +54,45 @@ class Reply(Enum):
 
     RebootImminent = 3
 
+    ClockMapping = 8
+
+    EthernetStatistics = 9
+
+    ThreadList = 10
+
+    HeapStatistics = 11
+    HeapCorruption = 12
+
+    PerfCounters = 13
+
+
+class PerfEvent(IntFlag):
+    """Events counted by the performance counters of the comms CPU."""
+    LA = 0x0010     # load access
+    SA = 0x0020     # store access
+    IF = 0x0040     # instruction fetch
+    DCM = 0x0080    # data cache miss
+    ICM = 0x0100    # instruction cache miss
+    IFS = 0x0200    # instruction fetch stall
+    LSUS = 0x0400   # load/store unit stall
+    BS = 0x0800     # branch stall
+    DTLBM = 0x1000  # data TLB miss
+    ITLBM = 0x2000  # instruction TLB miss
+    DDS = 0x4000    # data dependency stall
+
+
+class ThreadPriority(Enum):
+    NORMAL = 0
+    HIGH = 1
+
+
+class ThreadState(Enum):
+    RUNNING = 0
+    RUNNABLE = 1
+    WAITING_FOR_EVENT = 2
+    WAITING_FOR_TIMEOUT = 3
+    TERMINATED = 4
+
 
 class LogLevel(Enum):
     OFF = 0
@@ -120,6 +169,10 @@ class CommMgmt:
         (value, ) = struct.unpack(">l", self._read(4))
         return value
 
+    def _read_int64(self):
+        (value, ) = struct.unpack(">q", self._read(8))
+        return value
+
     def _read_bytes(self):
         return self._read(self._read_int32())
 
@@ -199,24 +252,81 @@ class CommMgmt:
         self._write_header(Request.StopProfiler)
         self._read_expect(Reply.Success)
 
-    def get_profile(self):
-        self._write_header(Request.GetProfile)
+    def _read_profile(self):
         self._read_expect(Reply.Profile)
 
-        hits = {}
+        version = self._read(1)[0]
+        if version != 2:
+            raise IOError("Unsupported profile version {}".format(version))
+        metadata = {
+            "interval": self._read_int32(),
+            "samples": self._read_int64(),
+            "dropped": self._read_int64(),
+            "duration": self._read_int64(),
+            "ident": self._read_string()
+        }
+
+        threads = [self._read_string() for _ in range(self._read_int32())]
+
+        hits = {thread: {} for thread in threads}
         for _ in range(self._read_int32()):
+            thread = threads[self._read(1)[0]]
             addr = self._read_int32()
             count = self._read_int32()
-            hits[addr] = count
+            hits[thread][addr] = count
 
-        edges = {}
+        edges = {thread: {} for thread in threads}
         for _ in range(self._read_int32()):
+            thread = threads[self._read(1)[0]]
             caller = self._read_int32()
             callee = self._read_int32()
             count = self._read_int32()
-            edges[(caller, callee)] = count
+            edges[thread][(caller, callee)] = count
+
+        return hits, edges, metadata
+
+    def get_profile(self):
+        """Return the hits and edges recorded by the profiler in all threads,
+        and a dictionary with the sampling interval (in microseconds), the
+        number of samples taken and of samples dropped because the hit or edge
+        storage was full, the time since the profiler was started (in
+        microseconds), and the identifier of the firmware the addresses belong
+        to."""
+        thread_hits, thread_edges, metadata = self.get_profile_per_thread()
+        hits = {}
+        for counts in thread_hits.values():
+            for addr, count in counts.items():
+                hits[addr] = hits.get(addr, 0) + count
+        edges = {}
+        for counts in thread_edges.values():
+            for edge, count in counts.items():
+                edges[edge] = edges.get(edge, 0) + count
+        return hits, edges, metadata
+
+    def get_profile_per_thread(self):
+        """Like :meth:`get_profile`, but return the hits and edges each as a
+        dictionary mapping the name of the thread the samples were taken in
+        to the hit or edge counts of that thread."""
+        self._write_header(Request.GetProfile)
+        return self._read_profile()
 
-        return hits, edges
+    def stream_profile(self, interval, window, hits_size, edges_size):
+        """Start the profiler and yield the profile of every window of
+        ``window`` milliseconds as it closes, in the format returned by
+        :meth:`get_profile_per_thread`. The profiler is stopped when the connection is
+        closed.
+
+        The core device allocates twice the hit and edge storage, so that one
+        window can be sent while the next one is recorded."""
+        self._write_header(Request.StreamProfile)
+        self._write_int32(interval)
+        self._write_int32(window)
+        self._write_int32(hits_size)
+        self._write_int32(edges_size)
+        self._read_expect(Reply.Success)
+
+        while True:
+            yield self._read_profile()
 
     def hotswap(self, firmware):
         self._write_header(Request.Hotswap)
@@ -228,4 +338,111 @@ class CommMgmt:
         self._read_expect(Reply.RebootImminent)
 
     def debug_allocator(self):
+        """Return a dictionary of heap statistics of the runtime: the bytes
+        used by busy blocks, free blocks and block headers, the number of busy
+        and free blocks, the size of the largest free block (counting adjacent
+        free blocks as one), and the percentage of free memory outside of that
+        largest block."""
         self._write_header(Request.DebugAllocator)
+        self._read_expect(Reply.HeapStatistics)
+        stats = dict()
+        for key in ["busy", "idle", "meta", "busy_blocks", "idle_blocks",
+                    "largest_idle", "fragmentation"]:
+            stats[key] = self._read_int32()
+        return stats
+
+    def check_heap(self):
+        """Check the integrity of the runtime heap. Return ``None`` if no
+        corruption was found, or a description of the first corrupted block.
+
+        Overruns and writes to freed memory are only detected if the runtime
+        was built with the ``alloc_debug`` feature."""
+        self._write_header(Request.CheckHeap)
+        ty = self._read_header()
+        if ty == Reply.Success:
+            return None
+        elif ty == Reply.HeapCorruption:
+            return self._read_string()
+        else:
+            raise IOError("Incorrect reply from device: {} (expected {} or {})".
+                          format(ty, Reply.Success, Reply.HeapCorruption))
+
+    def get_clock_mapping(self):
+        """Return a simultaneous sample of the wall-clock time (in
+        microseconds since the UNIX epoch, or ``None`` if the core device is
+        not synchronized to an SNTP server), the runtime clock (in microseconds
+        since boot) and the RTIO counter (in machine units)."""
+        self._write_header(Request.GetClockMapping)
+        self._read_expect(Reply.ClockMapping)
+        synchronized = bool(self._read(1)[0])
+        utc_us = self._read_int64()
+        clock_us = self._read_int64()
+        rtio_counter = self._read_int64()
+        return (utc_us if synchronized else None), clock_us, rtio_counter
+
+    def get_ethernet_statistics(self):
+        """Return a dictionary of the cumulative Ethernet counters of the
+        core device and the PHY link status (``None`` if the PHY cannot be
+        queried on this board)."""
+        self._write_header(Request.GetEthernetStatistics)
+        self._read_expect(Reply.EthernetStatistics)
+        stats = dict()
+        stats["rx_frames"] = self._read_int64()
+        stats["tx_frames"] = self._read_int64()
+        stats["tx_full"] = self._read_int64()
+        stats["rx_preamble_errors"] = self._read_int32()
+        stats["rx_crc_errors"] = self._read_int32()
+        stats["rx_dropped"] = self._read_int32()
+        link_known = bool(self._read(1)[0])
+        link_up = bool(self._read(1)[0])
+        stats["phy_link_up"] = link_up if link_known else None
+        return stats
+
+    def configure_perf_counters(self, counters, kernel_only=False):
+        """Assign a combination of :class:`PerfEvent` to each of the first
+        ``len(counters)`` performance counters of the comms CPU and reset
+        them. Each counter counts the occurrences of any of its events.
+
+        If ``kernel_only`` is set, the counters only run while a kernel is
+        running, are reset when a kernel starts, and read back the values at
+        the end of the last kernel run."""
+        self._write_header(Request.ConfigurePerfCounters)
+        self._write_int8(kernel_only)
+        self._write_int32(len(counters))
+        for events in counters:
+            self._write_int32(events)
+        self._read_expect(Reply.Success)
+
+    def read_perf_counters(self):
+        """Return whether the performance counters only run while a kernel
+        is running, and a list of the :class:`PerfEvent` and (32-bit,
+        wrapping) value of each configured counter."""
+        self._write_header(Request.ReadPerfCounters)
+        self._read_expect(Reply.PerfCounters)
+        kernel_only = bool(self._read(1)[0])
+        counters = []
+        for _ in range(self._read_int32()):
+            events = PerfEvent(self._read_int32())
+            value = self._read_int32() & 0xffffffff
+            counters.append((events, value))
+        return kernel_only, counters
+
+    def get_threads(self):
+        """Return a list of the threads of the runtime scheduler, each as a
+        dictionary with the thread name, its :class:`ThreadState`, the time it
+        was spawned at (in milliseconds since boot), its
+        :class:`ThreadPriority`, and its stack size and stack high-water mark
+        (in bytes)."""
+        self._write_header(Request.GetThreads)
+        self._read_expect(Reply.ThreadList)
+        threads = []
+        for _ in range(self._read_int32()):
+            thread = dict()
+            thread["name"] = self._read_string()
+            thread["state"] = ThreadState(self._read(1)[0])
+            thread["spawned_at"] = self._read_int64()
+            thread["priority"] = ThreadPriority(self._read(1)[0])
+            thread["stack_size"] = self._read_int32()
+            thread["stack_used"] = self._read_int32()
+            threads.append(thread)
+        return threads
@@ -65,13 +65,18 @@ class Core:
     :param ref_multiplier: ratio between the RTIO fine timestamp frequency
         and the RTIO coarse timestamp frequency (e.g. SERDES multiplication
         factor).
+    :param forward_kernel_log: if true, the core device also sends the
+        ``core_log`` output of kernels to the host, where it is logged
+        under the ``kernel`` logger together with the rest of the
+        experiment's log.
     """
 
     kernel_invariants = {
         "core", "ref_period", "coarse_ref_period", "ref_multiplier",
     }
 
-    def __init__(self, dmgr, host, ref_period, ref_multiplier=8):
+    def __init__(self, dmgr, host, ref_period, ref_multiplier=8,
+                 forward_kernel_log=False):
         self.ref_period = ref_period
         self.ref_multiplier = ref_multiplier
         self.coarse_ref_period = ref_period*ref_multiplier
@@ -79,6 +84,7 @@ class Core:
             self.comm = CommKernelDummy()
         else:
             self.comm = CommKernel(host)
+        self.comm.forward_kernel_log = forward_kernel_log
 
         self.first_run = True
         self.dmgr = dmgr
@@ -126,12 +132,33 @@ class Core:
             self.comm.check_system_info()
             self.first_run = False
 
+        # Fail before loading, rather than with an unimplemented syscall
+        # on the core device.
+        if b"dma_record_start" in kernel_library or \
+                b"dma_playback" in kernel_library:
+            self.comm.check_capability("rtio_dma", "DMA")
+
         self.comm.load(kernel_library)
         self.comm.run()
         self.comm.serve(embedding_map, symbolizer, demangler)
 
         return result
 
+    def stage(self, function, args, kwargs):
+        """Compile a kernel and upload it while the current kernel runs, so
+        that the core device loads it as soon as the current kernel ends.
+
+        This is meant to be called from an RPC. A following :meth:`run`
+        with the same function and arguments then starts without waiting
+        for the kernel to be uploaded and loaded."""
+        @rpc(flags={"async"})
+        def set_result(new_result):
+            pass
+
+        _, kernel_library, _, _ = \
+            self.compile(function, args, kwargs, set_result)
+        self.comm.stage(kernel_library)
+
     @portable
     def seconds_to_mu(self, seconds):
         """Convert seconds to the corresponding number of machine units
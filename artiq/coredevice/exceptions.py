@@ -111,6 +111,10 @@ class ClockFailure(Exception):
     """Raised when RTIO PLL has lost lock."""
 
 
+class KernelAborted(Exception):
+    """Raised when the kernel was stopped at the request of the host."""
+
+
 class I2CError(Exception):
     """Raised when a I2C transaction fails."""
     pass
@@ -62,10 +62,12 @@ class CallgrindWriter:
             spec_ids[value] = len(spec_ids) + 1
             self._write("{}=({}) {}", spec, spec_ids[value], value)
 
-    def header(self):
+    def header(self, descriptions=None):
         self._write("# callgrind format")
         self._write("version: 1")
         self._write("creator: ARTIQ")
+        for key, value in descriptions or []:
+            self._write("desc: {}: {}", key, value)
         self._write("positions: instr line")
         self._write("events: Hits")
         self._write("")
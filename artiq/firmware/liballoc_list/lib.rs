@@ -1,6 +1,12 @@
 #![no_std]
 
-use core::{ptr, mem, fmt};
+#[cfg(test)]
+#[macro_use]
+extern crate std;
+
+use core::{ptr, mem, fmt, cmp};
+#[cfg(feature = "debug")]
+use core::slice;
 use core::alloc::{GlobalAlloc, Layout};
 
 // The minimum alignment guaranteed by the architecture.
@@ -9,17 +15,150 @@ const MIN_ALIGN: usize = 4;
 const MAGIC_FREE: usize = 0xDEADDEAD;
 const MAGIC_BUSY: usize = 0xFEEDFEED;
 
+// With the `debug` feature, every busy block ends with at least CANARY_SIZE bytes
+// of CANARY past the requested size, and the contents of every free block,
+// including the headers of joined blocks, are overwritten with POISON.
+#[cfg(feature = "debug")]
+const CANARY_SIZE: usize = 4;
+#[cfg(not(feature = "debug"))]
+const CANARY_SIZE: usize = 0;
+#[cfg(feature = "debug")]
+const CANARY: u8 = 0xCA;
+#[cfg(feature = "debug")]
+const POISON: u8 = 0x55;
+#[cfg(feature = "debug")]
+const POISON_MAGIC: usize = 0x55555555;
+
 #[derive(Debug)]
 struct Header {
     magic: usize,
     size:  usize,
-    next:  *mut Header
+    next:  *mut Header,
+    #[cfg(feature = "debug")]
+    requested: usize
+}
+
+impl Header {
+    unsafe fn data(this: *mut Header) -> *mut u8 {
+        this.offset(1) as *mut u8
+    }
+
+    unsafe fn set_busy(this: *mut Header, _requested: usize) {
+        (*this).magic = MAGIC_BUSY;
+        #[cfg(feature = "debug")]
+        {
+            (*this).requested = _requested;
+            ptr::write_bytes(Header::data(this).offset(_requested as isize), CANARY,
+                             (*this).size - _requested)
+        }
+    }
+
+    unsafe fn set_free(this: *mut Header) {
+        (*this).magic = MAGIC_FREE;
+        poison(Header::data(this), (*this).size)
+    }
+
+    // Checks the canaries of a busy block or the poison of a free block.
+    #[cfg(feature = "debug")]
+    unsafe fn check(this: *mut Header) -> Result<(), Corruption> {
+        fn filled(ptr: *const u8, len: usize, byte: u8) -> bool {
+            unsafe { slice::from_raw_parts(ptr, len).iter().all(|&b| b == byte) }
+        }
+
+        let data = Header::data(this);
+        match (*this).magic {
+            MAGIC_BUSY => {
+                let requested = (*this).requested;
+                if requested > (*this).size ||
+                        !filled(data.offset(requested as isize), (*this).size - requested,
+                                CANARY) {
+                    return Err(Corruption::Overrun(this as usize))
+                }
+            }
+            MAGIC_FREE => {
+                if !filled(data, (*this).size, POISON) {
+                    return Err(Corruption::UseAfterFree(this as usize))
+                }
+            }
+            _ => return Err(Corruption::Header(this as usize))
+        }
+        Ok(())
+    }
+
+    #[cfg(not(feature = "debug"))]
+    unsafe fn check(this: *mut Header) -> Result<(), Corruption> {
+        match (*this).magic {
+            MAGIC_BUSY | MAGIC_FREE => Ok(()),
+            _ => Err(Corruption::Header(this as usize))
+        }
+    }
+}
+
+#[cfg(feature = "debug")]
+unsafe fn poison(ptr: *mut u8, len: usize) {
+    ptr::write_bytes(ptr, POISON, len)
+}
+
+#[cfg(not(feature = "debug"))]
+unsafe fn poison(_ptr: *mut u8, _len: usize) {}
+
+/// An inconsistency found by `ListAlloc::check`; each variant holds the address
+/// of the affected block header.
+#[derive(Debug, Clone, Copy)]
+pub enum Corruption {
+    /// The block header was overwritten.
+    Header(usize),
+    /// Data was written past the end of the allocation.
+    Overrun(usize),
+    /// The block was written to after it was freed.
+    UseAfterFree(usize),
+}
+
+impl fmt::Display for Corruption {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Corruption::Header(addr) =>
+                write!(f, "corrupted block header at {:#x}", addr),
+            Corruption::Overrun(addr) =>
+                write!(f, "write past the end of the block at {:#x}", addr),
+            Corruption::UseAfterFree(addr) =>
+                write!(f, "write to the freed block at {:#x}", addr),
+        }
+    }
 }
 
 pub struct ListAlloc {
     root:  *mut Header
 }
 
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Stats {
+    pub busy: usize,
+    pub idle: usize,
+    pub meta: usize,
+    pub busy_blocks: usize,
+    pub idle_blocks: usize,
+    /// Size of the largest free block, taking into account adjacent free blocks that
+    /// will be joined. Allocations are rounded up to a multiple of the block header
+    /// size, so the largest allocation that can succeed may be slightly smaller.
+    pub largest_idle: usize,
+}
+
+impl Stats {
+    pub fn total(&self) -> usize {
+        self.busy + self.idle + self.meta
+    }
+
+    /// Returns the percentage of free memory that is not part of the largest free block.
+    pub fn fragmentation(&self) -> usize {
+        if self.idle == 0 {
+            0
+        } else {
+            100 - cmp::min(100, self.largest_idle * 100 / self.idle)
+        }
+    }
+}
+
 pub const EMPTY: ListAlloc = ListAlloc { root: 0 as *mut Header };
 
 impl ListAlloc {
@@ -28,29 +167,72 @@ impl ListAlloc {
         if size < header_size * 2 { return }
 
         let curr = ptr as *mut Header;
-        (*curr).magic = MAGIC_FREE;
         (*curr).size  = size - header_size;
         (*curr).next  = self.root;
+        Header::set_free(curr);
         self.root = curr;
     }
 
     pub unsafe fn add_range(&mut self, begin: *mut u8, end: *mut u8) {
         self.add(begin, end as usize - begin as usize)
     }
+
+    pub fn stats(&self) -> Stats {
+        let header_size = mem::size_of::<Header>();
+        let mut stats = Stats::default();
+        unsafe {
+            // Size of the run of free blocks ending at the current one, as if joined.
+            let mut run = None;
+            let mut curr = self.root;
+            while !curr.is_null() {
+                stats.meta += header_size;
+                match (*curr).magic {
+                    MAGIC_FREE => {
+                        stats.idle += (*curr).size;
+                        stats.idle_blocks += 1;
+                        run = Some(match run {
+                            None => (*curr).size,
+                            Some(run) => run + header_size + (*curr).size
+                        });
+                        stats.largest_idle = cmp::max(stats.largest_idle, run.unwrap());
+                    }
+                    MAGIC_BUSY => {
+                        stats.busy += (*curr).size;
+                        stats.busy_blocks += 1;
+                        run = None;
+                    }
+                    _ => break
+                }
+                curr = (*curr).next;
+            }
+        }
+        stats
+    }
+
+    /// Checks the header of every block. With the `debug` feature, also checks
+    /// the canaries of every busy block and the poison of every free block.
+    pub fn check(&self) -> Result<(), Corruption> {
+        unsafe {
+            let mut curr = self.root;
+            while !curr.is_null() {
+                Header::check(curr)?;
+                curr = (*curr).next;
+            }
+        }
+        Ok(())
+    }
 }
 
 unsafe impl GlobalAlloc for ListAlloc {
     unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
-        if layout.align() > MIN_ALIGN {
-            panic!("cannot allocate with alignment {}", layout.align())
-        }
-
         let header_size = mem::size_of::<Header>();
+        let align = cmp::max(layout.align(), MIN_ALIGN);
         let size;
-        if layout.size() % header_size != 0 {
-            size = layout.size() + header_size - (layout.size() % header_size);
+        let padded_size = layout.size() + CANARY_SIZE;
+        if padded_size % header_size != 0 {
+            size = padded_size + header_size - (padded_size % header_size);
         } else {
-            size = layout.size()
+            size = padded_size
         }
 
         let mut curr = self.root;
@@ -61,12 +243,39 @@ unsafe impl GlobalAlloc for ListAlloc {
                     let mut next = (*curr).next;
                     while !next.is_null() && (*next).magic == MAGIC_FREE {
                         // Join
-                        (*next).magic = 0;
                         (*curr).size += (*next).size + header_size;
                         (*curr).next  = (*next).next;
+                        (*next).magic = 0;
+                        poison(next as *mut u8, header_size);
                         next = (*curr).next;
                     }
 
+                    // If the data is misaligned, the block must be split so that
+                    // the data starts at an aligned address; the space before it
+                    // must be large enough to hold the header of the split block.
+                    let data = curr.offset(1) as usize;
+                    let padding = if data % align == 0 {
+                        0
+                    } else {
+                        let aligned = (data + header_size + align - 1) & !(align - 1);
+                        aligned - data
+                    };
+                    if (*curr).size < padding + size {
+                        curr = (*curr).next;
+                        continue
+                    }
+
+                    if padding > 0 {
+                        // Split off the padding, which remains free
+                        let next = (curr as *mut u8).offset(padding as isize) as *mut Header;
+                        (*next).magic = MAGIC_FREE;
+                        (*next).size  = (*curr).size - padding;
+                        (*next).next  = (*curr).next;
+                        (*curr).next  = next;
+                        (*curr).size  = padding - header_size;
+                        curr = next;
+                    }
+
                     if (*curr).size > size + header_size * 2 {
                         // Split
                         let offset = header_size + size;
@@ -79,8 +288,8 @@ unsafe impl GlobalAlloc for ListAlloc {
                     }
 
                     if (*curr).size >= size {
-                        (*curr).magic = MAGIC_BUSY;
-                        return curr.offset(1) as *mut u8
+                        Header::set_busy(curr, layout.size());
+                        return Header::data(curr)
                     }
                 },
                 _ => panic!("heap corruption detected at {:p}", curr)
@@ -94,10 +303,18 @@ unsafe impl GlobalAlloc for ListAlloc {
 
     unsafe fn dealloc(&self, ptr: *mut u8, _layout: Layout) {
         let curr = (ptr as *mut Header).offset(-1);
-        if (*curr).magic != MAGIC_BUSY {
-            panic!("heap corruption detected at {:p}", curr)
+        match (*curr).magic {
+            MAGIC_BUSY => (),
+            MAGIC_FREE => panic!("double free of {:p}", ptr),
+            // The block was freed and then joined with the block before it.
+            #[cfg(feature = "debug")]
+            POISON_MAGIC => panic!("double free of {:p}", ptr),
+            _ => panic!("heap corruption detected at {:p}", curr)
+        }
+        if let Err(err) = Header::check(curr) {
+            panic!("heap corruption detected: {}", err)
         }
-        (*curr).magic = MAGIC_FREE;
+        Header::set_free(curr);
     }
 }
 
@@ -134,3 +351,214 @@ impl fmt::Display for ListAlloc {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::vec::Vec;
+    use core::mem;
+    use core::alloc::{GlobalAlloc, Layout};
+    use super::*;
+
+    const HEAP_SIZE: usize = 4096;
+
+    #[repr(align(4096))]
+    struct Heap([u8; HEAP_SIZE]);
+
+    fn header_size() -> usize {
+        mem::size_of::<Header>()
+    }
+
+    // Size of the block that holds an allocation of `size` bytes.
+    fn block_size(size: usize) -> usize {
+        let padded_size = size + CANARY_SIZE;
+        (padded_size + header_size() - 1) / header_size() * header_size()
+    }
+
+    unsafe fn new_alloc(heap: *mut Heap) -> ListAlloc {
+        let mut alloc = EMPTY;
+        alloc.add(heap as *mut u8, HEAP_SIZE);
+        alloc
+    }
+
+    // Returns the address, data size and state of every block, in list order.
+    fn blocks(alloc: &ListAlloc) -> Vec<(usize, usize, bool)> {
+        let mut blocks = Vec::new();
+        unsafe {
+            let mut curr = alloc.root;
+            while !curr.is_null() {
+                blocks.push((curr as usize, (*curr).size, (*curr).magic == MAGIC_BUSY));
+                curr = (*curr).next;
+            }
+        }
+        blocks
+    }
+
+    // Checks that the blocks tile the heap without gaps or overlaps.
+    fn check_tiling(alloc: &ListAlloc, heap: *mut Heap) {
+        let mut addr = heap as usize;
+        for (block, size, _) in blocks(alloc) {
+            assert_eq!(block, addr);
+            addr += header_size() + size;
+        }
+        assert_eq!(addr, heap as usize + HEAP_SIZE);
+        alloc.check().unwrap();
+    }
+
+    #[test]
+    fn stats() {
+        static mut HEAP: Heap = Heap([0; HEAP_SIZE]);
+        let heap = unsafe { &mut HEAP as *mut Heap };
+        let alloc = unsafe { new_alloc(heap) };
+        let layout = Layout::from_size_align(64, 4).unwrap();
+        let (h, b) = (header_size(), block_size(64));
+
+        let stats = alloc.stats();
+        assert_eq!((stats.busy, stats.idle, stats.meta), (0, HEAP_SIZE - h, h));
+        assert_eq!((stats.busy_blocks, stats.idle_blocks), (0, 1));
+        assert_eq!(stats.largest_idle, HEAP_SIZE - h);
+        assert_eq!(stats.fragmentation(), 0);
+
+        let (p1, p2, p3) = unsafe { (alloc.alloc(layout), alloc.alloc(layout), alloc.alloc(layout)) };
+        assert!(!p1.is_null() && !p2.is_null() && !p3.is_null());
+        let rest = HEAP_SIZE - 4 * h - 3 * b;
+        let stats = alloc.stats();
+        assert_eq!((stats.busy, stats.idle, stats.meta), (3 * b, rest, 4 * h));
+        assert_eq!((stats.busy_blocks, stats.idle_blocks), (3, 1));
+        assert_eq!(stats.largest_idle, rest);
+        assert_eq!(stats.fragmentation(), 0);
+        assert_eq!(stats.total(), HEAP_SIZE);
+
+        // A hole between two busy blocks.
+        unsafe { alloc.dealloc(p2, layout) }
+        let stats = alloc.stats();
+        assert_eq!((stats.busy_blocks, stats.idle_blocks), (2, 2));
+        assert_eq!(stats.idle, rest + b);
+        assert_eq!(stats.largest_idle, rest);
+        assert_eq!(stats.fragmentation(), 100 - rest * 100 / (rest + b));
+        assert!(stats.fragmentation() > 0);
+
+        // Adjacent free blocks count as one for the largest allocation.
+        unsafe { alloc.dealloc(p1, layout) }
+        let stats = alloc.stats();
+        assert_eq!((stats.busy_blocks, stats.idle_blocks), (1, 3));
+        assert_eq!(stats.largest_idle, rest);
+        unsafe { alloc.dealloc(p3, layout) }
+        let stats = alloc.stats();
+        assert_eq!((stats.busy, stats.busy_blocks, stats.idle_blocks), (0, 0, 4));
+        assert_eq!(stats.largest_idle, HEAP_SIZE - h);
+        assert_eq!(stats.fragmentation(), 0);
+        assert_eq!(stats.total(), HEAP_SIZE);
+
+        // The next allocation joins them.
+        let p4 = unsafe { alloc.alloc(layout) };
+        assert_eq!(p4, p1);
+        let stats = alloc.stats();
+        assert_eq!((stats.busy_blocks, stats.idle_blocks), (1, 1));
+        assert_eq!(stats.largest_idle, HEAP_SIZE - 2 * h - b);
+        check_tiling(&alloc, heap);
+    }
+
+    #[test]
+    fn exhaustion() {
+        static mut HEAP: Heap = Heap([0; HEAP_SIZE]);
+        let heap = unsafe { &mut HEAP as *mut Heap };
+        let alloc = unsafe { new_alloc(heap) };
+
+        let stats = alloc.stats();
+        let too_large = Layout::from_size_align(stats.largest_idle + 1, 4).unwrap();
+        assert!(unsafe { alloc.alloc(too_large) }.is_null());
+        let largest_size = stats.largest_idle / header_size() * header_size() - CANARY_SIZE;
+        let largest = Layout::from_size_align(largest_size, 4).unwrap();
+        let ptr = unsafe { alloc.alloc(largest) };
+        assert!(!ptr.is_null());
+        assert!(alloc.stats().largest_idle < header_size());
+        unsafe { alloc.dealloc(ptr, largest) }
+        check_tiling(&alloc, heap);
+    }
+
+    #[test]
+    fn alignment() {
+        static mut HEAP: Heap = Heap([0; HEAP_SIZE]);
+        let heap = unsafe { &mut HEAP as *mut Heap };
+        let alloc = unsafe { new_alloc(heap) };
+
+        let mut align = 4;
+        while align <= 256 {
+            // Misalign the free space, so that most requests need padding.
+            let small = Layout::from_size_align(1, 4).unwrap();
+            let spacer = unsafe { alloc.alloc(small) };
+            assert!(!spacer.is_null());
+
+            let layout = Layout::from_size_align(24, align).unwrap();
+            let ptr = unsafe { alloc.alloc(layout) };
+            assert!(!ptr.is_null(), "cannot allocate with alignment {}", align);
+            assert_eq!(ptr as usize % align, 0, "misaligned for alignment {}", align);
+            unsafe { ptr::write_bytes(ptr, 0xAA, layout.size()) }
+
+            // The padding in front of the allocation stays in the list as a free block.
+            let blocks = blocks(&alloc);
+            let index = blocks.iter()
+                .position(|&(addr, _, _)| addr + header_size() == ptr as usize)
+                .unwrap();
+            let (prev_addr, prev_size, prev_busy) = blocks[index - 1];
+            if prev_addr != spacer as usize - header_size() {
+                assert!(!prev_busy, "padding before alignment {} is not free", align);
+                assert_eq!(blocks[index - 2].0, spacer as usize - header_size());
+            }
+            assert_eq!(prev_addr + header_size() + prev_size + header_size(), ptr as usize);
+            check_tiling(&alloc, heap);
+
+            unsafe {
+                alloc.dealloc(ptr, layout);
+                alloc.dealloc(spacer, small);
+            }
+            align *= 2;
+        }
+
+        // Once everything is freed and joined, no memory is lost to padding.
+        let stats = alloc.stats();
+        assert_eq!(stats.busy_blocks, 0);
+        assert_eq!(stats.largest_idle, HEAP_SIZE - header_size());
+        let small = Layout::from_size_align(1, 4).unwrap();
+        let ptr = unsafe { alloc.alloc(small) };
+        assert_eq!(ptr as usize, heap as usize + header_size());
+        assert_eq!(alloc.stats().idle_blocks, 1);
+        unsafe { alloc.dealloc(ptr, small) }
+    }
+
+    #[test]
+    fn alignment_stress() {
+        static mut HEAP: Heap = Heap([0; HEAP_SIZE]);
+        let heap = unsafe { &mut HEAP as *mut Heap };
+        let alloc = unsafe { new_alloc(heap) };
+
+        let mut seed: u32 = 1;
+        let mut random = || {
+            seed ^= seed << 13;
+            seed ^= seed >> 17;
+            seed ^= seed << 5;
+            seed
+        };
+        let mut live: Vec<(*mut u8, Layout)> = Vec::new();
+        for _ in 0..5000 {
+            if live.len() < 8 && random() % 3 != 0 {
+                let align = 4 << (random() % 7);
+                let layout = Layout::from_size_align(1 + random() as usize % 128, align).unwrap();
+                let ptr = unsafe { alloc.alloc(layout) };
+                if ptr.is_null() { continue }
+                assert_eq!(ptr as usize % align, 0);
+                unsafe { ptr::write_bytes(ptr, 0xAA, layout.size()) }
+                live.push((ptr, layout));
+            } else if !live.is_empty() {
+                let index = random() as usize % live.len();
+                let (ptr, layout) = live.swap_remove(index);
+                unsafe { alloc.dealloc(ptr, layout) }
+            }
+            check_tiling(&alloc, heap);
+        }
+        for (ptr, layout) in live {
+            unsafe { alloc.dealloc(ptr, layout) }
+        }
+        assert_eq!(alloc.stats().largest_idle, HEAP_SIZE - header_size());
+    }
+}
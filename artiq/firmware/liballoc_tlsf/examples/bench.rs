@@ -0,0 +1,87 @@
+//! Compares TlsfAlloc with ListAlloc on the same random workload.
+//!
+//! Run with `cargo run --release --example bench`.
+
+extern crate alloc_list;
+extern crate alloc_tlsf;
+
+use std::time::Instant;
+use std::alloc::{GlobalAlloc, Layout};
+
+const HEAP_SIZE: usize = 1 << 20;
+const OPERATIONS: usize = 200000;
+
+struct Random(u32);
+
+impl Random {
+    fn next(&mut self) -> u32 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 17;
+        self.0 ^= self.0 << 5;
+        self.0
+    }
+}
+
+// Runs the workload and returns the time per operation in nanoseconds, and the
+// number of failed allocations.
+unsafe fn run<A: GlobalAlloc>(alloc: &A, max_live: usize, max_align_log2: u32) -> (f64, usize) {
+    let mut random = Random(1);
+    let mut live: Vec<(*mut u8, Layout)> = Vec::with_capacity(max_live);
+    let mut failed = 0;
+
+    let start = Instant::now();
+    for _ in 0..OPERATIONS {
+        if live.len() < max_live && random.next() % 2 == 0 {
+            let align = 4 << (random.next() % (max_align_log2 - 1));
+            let size = match random.next() % 4 {
+                0 => 1 + random.next() as usize % 16,
+                1 | 2 => 1 + random.next() as usize % 512,
+                _ => 1 + random.next() as usize % 4096
+            };
+            let layout = Layout::from_size_align(size, align).unwrap();
+            let ptr = alloc.alloc(layout);
+            if ptr.is_null() {
+                failed += 1
+            } else {
+                live.push((ptr, layout))
+            }
+        } else if !live.is_empty() {
+            let index = random.next() as usize % live.len();
+            let (ptr, layout) = live.swap_remove(index);
+            alloc.dealloc(ptr, layout)
+        }
+    }
+    let elapsed = start.elapsed();
+
+    for (ptr, layout) in live {
+        alloc.dealloc(ptr, layout)
+    }
+    let elapsed_ns = elapsed.as_secs() as f64 * 1e9 + elapsed.subsec_nanos() as f64;
+    (elapsed_ns / OPERATIONS as f64, failed)
+}
+
+fn heap() -> Vec<u8> {
+    vec![0; HEAP_SIZE]
+}
+
+fn main() {
+    println!("{} operations on a {} KiB heap, ns per operation (failed allocations)",
+             OPERATIONS, HEAP_SIZE / 1024);
+    println!("{:>9} {:>9} {:>20} {:>20}", "live", "align", "ListAlloc", "TlsfAlloc");
+    for &(max_live, max_align_log2) in [(16, 3), (64, 3), (256, 3), (64, 8), (256, 8)].iter() {
+        let mut list_heap = heap();
+        let mut list = alloc_list::EMPTY;
+        let mut tlsf_heap = heap();
+        let mut tlsf = alloc_tlsf::EMPTY;
+        let (list_ns, list_failed, tlsf_ns, tlsf_failed) = unsafe {
+            list.add(list_heap.as_mut_ptr(), HEAP_SIZE);
+            tlsf.add(tlsf_heap.as_mut_ptr(), HEAP_SIZE);
+            let (list_ns, list_failed) = run(&list, max_live, max_align_log2);
+            let (tlsf_ns, tlsf_failed) = run(&tlsf, max_live, max_align_log2);
+            (list_ns, list_failed, tlsf_ns, tlsf_failed)
+        };
+        println!("{:>9} {:>9} {:>13.1} ({:>4}) {:>13.1} ({:>4})",
+                 max_live, format!("4..{}", 1 << max_align_log2),
+                 list_ns, list_failed, tlsf_ns, tlsf_failed);
+    }
+}
@@ -0,0 +1,501 @@
+#![no_std]
+
+//! A two-level segregated fit (TLSF) allocator.
+//!
+//! Free blocks are kept in lists segregated by size, found through two levels of
+//! bitmaps, and neighbouring free blocks are joined as soon as a block is freed.
+//! Both allocation and deallocation therefore take bounded time, independent of
+//! the number of blocks in the heap.
+
+extern crate alloc_list;
+#[cfg(test)]
+#[macro_use]
+extern crate std;
+
+use core::{ptr, mem, fmt, cmp};
+use core::alloc::{GlobalAlloc, Layout};
+
+pub use alloc_list::{Stats, Corruption};
+
+const WORD: usize = mem::size_of::<usize>();
+
+// Every block starts with a header; the payload that follows it is aligned to ALIGN.
+const HEADER_SIZE: usize = 2 * WORD;
+const ALIGN: usize = HEADER_SIZE;
+// A free block stores the free list links in its payload.
+const MIN_PAYLOAD: usize = 2 * WORD;
+
+// Each first-level class (a power of two) is split into SL_COUNT second-level classes.
+const SL_LOG2: usize = 4;
+const SL_COUNT: usize = 1 << SL_LOG2;
+// Blocks smaller than SMALL_BLOCK_SIZE are all kept in the first first-level class.
+const FL_INDEX_SHIFT: usize = SL_LOG2 + 3;
+const SMALL_BLOCK_SIZE: usize = 1 << FL_INDEX_SHIFT;
+// Largest block is just below 1 << FL_INDEX_MAX bytes.
+const FL_INDEX_MAX: usize = 30;
+const FL_COUNT: usize = FL_INDEX_MAX - FL_INDEX_SHIFT + 1;
+
+const MAX_POOLS: usize = 4;
+
+const FLAG_FREE: usize = 1;
+const FLAG_MASK: usize = ALIGN - 1;
+
+#[derive(Debug)]
+#[repr(C)]
+struct Block {
+    prev_phys: *mut Block,
+    size:      usize,
+    // Only valid while the block is free.
+    next_free: *mut Block,
+    prev_free: *mut Block,
+}
+
+impl Block {
+    unsafe fn size(this: *mut Block) -> usize {
+        (*this).size & !FLAG_MASK
+    }
+
+    unsafe fn is_free(this: *mut Block) -> bool {
+        (*this).size & FLAG_FREE != 0
+    }
+
+    unsafe fn set_size(this: *mut Block, size: usize) {
+        (*this).size = size | ((*this).size & FLAG_MASK)
+    }
+
+    unsafe fn set_free(this: *mut Block, free: bool) {
+        if free {
+            (*this).size |= FLAG_FREE
+        } else {
+            (*this).size &= !FLAG_FREE
+        }
+    }
+
+    unsafe fn next_phys(this: *mut Block) -> *mut Block {
+        (this as usize + HEADER_SIZE + Block::size(this)) as *mut Block
+    }
+
+    unsafe fn payload(this: *mut Block) -> *mut u8 {
+        (this as usize + HEADER_SIZE) as *mut u8
+    }
+
+    unsafe fn from_payload(ptr: *mut u8) -> *mut Block {
+        (ptr as usize - HEADER_SIZE) as *mut Block
+    }
+}
+
+struct Control {
+    fl_bitmap: u32,
+    sl_bitmap: [u32; FL_COUNT],
+    blocks:    [[*mut Block; SL_COUNT]; FL_COUNT],
+    pools:     [*mut Block; MAX_POOLS],
+}
+
+fn fls(value: usize) -> usize {
+    mem::size_of::<usize>() * 8 - 1 - value.leading_zeros() as usize
+}
+
+fn align_up(value: usize, align: usize) -> usize {
+    (value + align - 1) & !(align - 1)
+}
+
+// Returns the classes of the list that a free block of `size` bytes belongs to.
+fn mapping_insert(size: usize) -> (usize, usize) {
+    if size < SMALL_BLOCK_SIZE {
+        (0, size / (SMALL_BLOCK_SIZE / SL_COUNT))
+    } else {
+        let fl = fls(size);
+        let sl = (size >> (fl - SL_LOG2)) ^ SL_COUNT;
+        (fl - (FL_INDEX_SHIFT - 1), sl)
+    }
+}
+
+// Returns the classes of the first list whose every block can hold `size` bytes.
+fn mapping_search(size: usize) -> Option<(usize, usize)> {
+    let size = if size >= SMALL_BLOCK_SIZE {
+        size + (1 << (fls(size) - SL_LOG2)) - 1
+    } else {
+        size
+    };
+    let (fl, sl) = mapping_insert(size);
+    if fl < FL_COUNT { Some((fl, sl)) } else { None }
+}
+
+impl Control {
+    unsafe fn insert_free(&mut self, block: *mut Block) {
+        let (fl, sl) = mapping_insert(Block::size(block));
+        let head = self.blocks[fl][sl];
+        (*block).next_free = head;
+        (*block).prev_free = ptr::null_mut();
+        if !head.is_null() {
+            (*head).prev_free = block;
+        }
+        self.blocks[fl][sl] = block;
+        self.fl_bitmap    |= 1 << fl;
+        self.sl_bitmap[fl] |= 1 << sl;
+    }
+
+    unsafe fn remove_free(&mut self, block: *mut Block) {
+        let (fl, sl) = mapping_insert(Block::size(block));
+        let (next, prev) = ((*block).next_free, (*block).prev_free);
+        if !next.is_null() {
+            (*next).prev_free = prev;
+        }
+        if !prev.is_null() {
+            (*prev).next_free = next;
+        }
+        if self.blocks[fl][sl] == block {
+            self.blocks[fl][sl] = next;
+            if next.is_null() {
+                self.sl_bitmap[fl] &= !(1 << sl);
+                if self.sl_bitmap[fl] == 0 {
+                    self.fl_bitmap &= !(1 << fl);
+                }
+            }
+        }
+    }
+
+    unsafe fn find_free(&self, size: usize) -> *mut Block {
+        let (mut fl, sl) = match mapping_search(size) {
+            Some(classes) => classes,
+            None => return ptr::null_mut()
+        };
+        let mut sl_map = self.sl_bitmap[fl] & (!0 << sl);
+        if sl_map == 0 {
+            let fl_map = self.fl_bitmap & (!0 << (fl + 1));
+            if fl_map == 0 {
+                return ptr::null_mut()
+            }
+            fl = fl_map.trailing_zeros() as usize;
+            sl_map = self.sl_bitmap[fl];
+        }
+        self.blocks[fl][sl_map.trailing_zeros() as usize]
+    }
+
+    // Shrinks `block` to `size` bytes, returning the rest to the free lists.
+    unsafe fn split(&mut self, block: *mut Block, size: usize) {
+        if Block::size(block) < size + HEADER_SIZE + MIN_PAYLOAD {
+            return
+        }
+
+        let rest = (Block::payload(block) as usize + size) as *mut Block;
+        (*rest).size = Block::size(block) - size - HEADER_SIZE;
+        (*rest).prev_phys = block;
+        Block::set_free(rest, true);
+        (*Block::next_phys(rest)).prev_phys = rest;
+        Block::set_size(block, size);
+        // The block following `block` is never free, since neighbouring free
+        // blocks are always joined.
+        self.insert_free(rest)
+    }
+
+    unsafe fn alloc(&mut self, layout: Layout) -> *mut u8 {
+        if layout.size() >= 1 << FL_INDEX_MAX || layout.align() >= 1 << FL_INDEX_MAX {
+            return ptr::null_mut()
+        }
+
+        let size  = cmp::max(align_up(layout.size(), ALIGN), MIN_PAYLOAD);
+        let align = cmp::max(layout.align(), ALIGN);
+        // Reserve room for a free block in front of the data if it must be realigned.
+        let search_size = if align > ALIGN { size + align + HEADER_SIZE + MIN_PAYLOAD }
+                          else { size };
+
+        let mut block = self.find_free(search_size);
+        if block.is_null() {
+            return ptr::null_mut()
+        }
+        self.remove_free(block);
+
+        let payload = Block::payload(block) as usize;
+        let mut aligned = align_up(payload, align);
+        if aligned != payload && aligned - payload < HEADER_SIZE + MIN_PAYLOAD {
+            aligned = align_up(payload + HEADER_SIZE + MIN_PAYLOAD, align);
+        }
+        if aligned != payload {
+            // Split off the space in front of the data, which remains free.
+            let gap = aligned - payload;
+            let next = (block as usize + gap) as *mut Block;
+            (*next).size = Block::size(block) - gap;
+            (*next).prev_phys = block;
+            (*Block::next_phys(next)).prev_phys = next;
+            Block::set_size(block, gap - HEADER_SIZE);
+            self.insert_free(block);
+            block = next;
+        }
+
+        self.split(block, size);
+        Block::set_free(block, false);
+        Block::payload(block)
+    }
+
+    unsafe fn dealloc(&mut self, ptr: *mut u8) {
+        let mut block = Block::from_payload(ptr);
+        if Block::is_free(block) {
+            panic!("double free of {:p}", ptr)
+        }
+        Block::set_free(block, true);
+
+        let prev = (*block).prev_phys;
+        if !prev.is_null() && Block::is_free(prev) {
+            self.remove_free(prev);
+            Block::set_size(prev, Block::size(prev) + HEADER_SIZE + Block::size(block));
+            block = prev;
+            (*Block::next_phys(block)).prev_phys = block;
+        }
+
+        let next = Block::next_phys(block);
+        if Block::is_free(next) {
+            self.remove_free(next);
+            Block::set_size(block, Block::size(block) + HEADER_SIZE + Block::size(next));
+            (*Block::next_phys(block)).prev_phys = block;
+        }
+
+        self.insert_free(block)
+    }
+}
+
+pub struct TlsfAlloc {
+    control: *mut Control
+}
+
+pub const EMPTY: TlsfAlloc = TlsfAlloc { control: 0 as *mut Control };
+
+impl TlsfAlloc {
+    pub unsafe fn add(&mut self, ptr: *mut u8, size: usize) {
+        let mut begin = align_up(ptr as usize, ALIGN);
+        let mut end = (ptr as usize + size) & !(ALIGN - 1);
+
+        if self.control.is_null() {
+            // The first pool also holds the allocator state.
+            let control_size = align_up(mem::size_of::<Control>(), ALIGN);
+            if end < begin + control_size { return }
+            self.control = begin as *mut Control;
+            ptr::write(self.control, Control {
+                fl_bitmap: 0,
+                sl_bitmap: [0; FL_COUNT],
+                blocks:    [[ptr::null_mut(); SL_COUNT]; FL_COUNT],
+                pools:     [ptr::null_mut(); MAX_POOLS],
+            });
+            begin += control_size;
+        }
+        let control = &mut *self.control;
+
+        let slot = match control.pools.iter().position(|pool| pool.is_null()) {
+            Some(slot) => slot,
+            None => return
+        };
+        // The pool consists of one free block followed by an empty busy block that
+        // stops the free block from being joined with whatever lies beyond the pool.
+        if end < begin + 2 * HEADER_SIZE + MIN_PAYLOAD { return }
+        end = cmp::min(end, begin.saturating_add((1 << FL_INDEX_MAX) - ALIGN));
+
+        let block = begin as *mut Block;
+        (*block).prev_phys = ptr::null_mut();
+        (*block).size = end - begin - 2 * HEADER_SIZE;
+        Block::set_free(block, true);
+        let sentinel = Block::next_phys(block);
+        (*sentinel).prev_phys = block;
+        (*sentinel).size = 0;
+
+        control.pools[slot] = block;
+        control.insert_free(block)
+    }
+
+    pub unsafe fn add_range(&mut self, begin: *mut u8, end: *mut u8) {
+        self.add(begin, end as usize - begin as usize)
+    }
+
+    unsafe fn for_each_block<F: FnMut(*mut Block)>(&self, mut f: F) {
+        if self.control.is_null() { return }
+        for &pool in (*self.control).pools.iter().filter(|pool| !pool.is_null()) {
+            let mut block = pool;
+            while Block::size(block) != 0 {
+                f(block);
+                block = Block::next_phys(block);
+            }
+        }
+    }
+
+    pub fn stats(&self) -> Stats {
+        let mut stats = Stats::default();
+        if !self.control.is_null() {
+            stats.meta += align_up(mem::size_of::<Control>(), ALIGN);
+        }
+        unsafe {
+            self.for_each_block(|block| {
+                stats.meta += HEADER_SIZE;
+                if Block::is_free(block) {
+                    stats.idle += Block::size(block);
+                    stats.idle_blocks += 1;
+                    stats.largest_idle = cmp::max(stats.largest_idle, Block::size(block));
+                } else {
+                    stats.busy += Block::size(block);
+                    stats.busy_blocks += 1;
+                }
+            })
+        }
+        stats
+    }
+
+    /// Checks that the blocks of every pool are consistently linked and that no
+    /// two free blocks are adjacent.
+    pub fn check(&self) -> Result<(), Corruption> {
+        if self.control.is_null() { return Ok(()) }
+        unsafe {
+            for &pool in (*self.control).pools.iter().filter(|pool| !pool.is_null()) {
+                let mut block = pool;
+                while Block::size(block) != 0 {
+                    let next = Block::next_phys(block);
+                    if (*next).prev_phys != block ||
+                            (Block::is_free(block) && Block::is_free(next)) {
+                        return Err(Corruption::Header(next as usize))
+                    }
+                    block = next;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+unsafe impl GlobalAlloc for TlsfAlloc {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        if self.control.is_null() {
+            return ptr::null_mut()
+        }
+        (*self.control).alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, _layout: Layout) {
+        (*self.control).dealloc(ptr)
+    }
+}
+
+impl fmt::Display for TlsfAlloc {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut result = Ok(());
+        unsafe {
+            self.for_each_block(|block| {
+                if result.is_ok() {
+                    let desc = if Block::is_free(block) { "IDLE" } else { "BUSY" };
+                    result = write!(f, "{} {:p} + {:#x} + {:#x}\n",
+                                    desc, block, HEADER_SIZE, Block::size(block));
+                }
+            })
+        }
+        result?;
+
+        let stats = self.stats();
+        write!(f, " === busy: {:#x} idle: {:#x} meta: {:#x} total: {:#x}\n",
+               stats.busy, stats.idle, stats.meta, stats.total())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::vec::Vec;
+    use core::alloc::{GlobalAlloc, Layout};
+    use super::*;
+
+    const HEAP_SIZE: usize = 1 << 20;
+
+    #[repr(align(4096))]
+    struct Heap([u8; HEAP_SIZE]);
+
+    // Checks that the pool is covered by the control block and the blocks, and
+    // returns the number of bytes of the largest free block.
+    fn check_tiling(alloc: &TlsfAlloc, heap: *mut Heap) -> usize {
+        alloc.check().unwrap();
+        let stats = alloc.stats();
+        // The sentinel block header at the end of the pool is not counted.
+        assert_eq!(stats.total() + HEADER_SIZE, HEAP_SIZE);
+        let mut addr = heap as usize + align_up(mem::size_of::<Control>(), ALIGN);
+        unsafe {
+            alloc.for_each_block(|block| {
+                assert_eq!(block as usize, addr);
+                addr += HEADER_SIZE + Block::size(block);
+            })
+        }
+        assert_eq!(addr + HEADER_SIZE, heap as usize + HEAP_SIZE);
+        stats.largest_idle
+    }
+
+    #[test]
+    fn stress() {
+        static mut HEAP: Heap = Heap([0; HEAP_SIZE]);
+        let heap = unsafe { &mut HEAP as *mut Heap };
+        let mut alloc = EMPTY;
+        unsafe { alloc.add(heap as *mut u8, HEAP_SIZE) }
+        let initial = check_tiling(&alloc, heap);
+        assert_eq!(alloc.stats().idle_blocks, 1);
+
+        let mut seed: u32 = 1;
+        let mut random = || {
+            seed ^= seed << 13;
+            seed ^= seed >> 17;
+            seed ^= seed << 5;
+            seed
+        };
+        let mut live: Vec<(*mut u8, Layout, u8)> = Vec::new();
+        for step in 0..20000 {
+            if live.len() < 256 && random() % 2 == 0 {
+                let align = 1 << (random() % 13);
+                let size = match random() % 4 {
+                    0 => 1 + random() as usize % 16,
+                    1 | 2 => 1 + random() as usize % 512,
+                    _ => 1 + random() as usize % 16384
+                };
+                let layout = Layout::from_size_align(size, align).unwrap();
+                let ptr = unsafe { alloc.alloc(layout) };
+                if ptr.is_null() { continue }
+                assert_eq!(ptr as usize % align, 0, "misaligned for alignment {}", align);
+                let fill = step as u8;
+                unsafe { ptr::write_bytes(ptr, fill, size) }
+                live.push((ptr, layout, fill));
+            } else if !live.is_empty() {
+                let index = random() as usize % live.len();
+                let (ptr, layout, fill) = live.swap_remove(index);
+                // Overlapping allocations would have overwritten the contents.
+                for offset in 0..layout.size() {
+                    assert_eq!(unsafe { *ptr.offset(offset as isize) }, fill);
+                }
+                unsafe { alloc.dealloc(ptr, layout) }
+            }
+            if step % 100 == 0 {
+                check_tiling(&alloc, heap);
+            }
+        }
+
+        for (ptr, layout, _) in live {
+            unsafe { alloc.dealloc(ptr, layout) }
+        }
+        // Every block has been joined back.
+        assert_eq!(check_tiling(&alloc, heap), initial);
+        let stats = alloc.stats();
+        assert_eq!((stats.busy_blocks, stats.idle_blocks), (0, 1));
+    }
+
+    #[test]
+    fn exhaustion() {
+        static mut HEAP: Heap = Heap([0; HEAP_SIZE]);
+        let heap = unsafe { &mut HEAP as *mut Heap };
+        let mut alloc = EMPTY;
+        unsafe { alloc.add(heap as *mut u8, HEAP_SIZE) }
+
+        let layout = Layout::from_size_align(4096, 4).unwrap();
+        let mut ptrs = Vec::new();
+        loop {
+            let ptr = unsafe { alloc.alloc(layout) };
+            if ptr.is_null() { break }
+            ptrs.push(ptr);
+        }
+        assert!(ptrs.len() > HEAP_SIZE / (4096 + HEADER_SIZE) - 2);
+        let too_large = Layout::from_size_align(HEAP_SIZE, 4).unwrap();
+        assert!(unsafe { alloc.alloc(too_large) }.is_null());
+        for ptr in ptrs {
+            unsafe { alloc.dealloc(ptr, layout) }
+        }
+        check_tiling(&alloc, heap);
+        assert_eq!(alloc.stats().idle_blocks, 1);
+    }
+}
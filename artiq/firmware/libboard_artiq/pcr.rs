@@ -22,8 +22,15 @@ bitflags! {
     }
 }
 
+pub const MAX_COUNTERS: u32 = 8;
+
 fn is_valid(index: u32) -> bool {
-    index < 8 && unsafe { mfspr(SPR_PCMR0 + index) } & SPR_PCMR_CP != 0
+    index < MAX_COUNTERS && unsafe { mfspr(SPR_PCMR0 + index) } & SPR_PCMR_CP != 0
+}
+
+/// Returns the number of performance counters implemented by the CPU.
+pub fn count() -> u32 {
+    (0..MAX_COUNTERS).take_while(|&index| is_valid(index)).count() as u32
 }
 
 #[inline]
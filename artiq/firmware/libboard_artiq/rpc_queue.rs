@@ -31,6 +31,16 @@ pub fn full() -> bool {
     unsafe { next(read_volatile(SEND_MAILBOX)) == read_volatile(RECV_MAILBOX) }
 }
 
+/// Returns the number of messages waiting in the queue.
+pub fn len() -> usize {
+    let chunks = (QUEUE_END - QUEUE_BEGIN + QUEUE_CHUNK - 1) / QUEUE_CHUNK;
+    let index = |addr: usize| (addr - QUEUE_BEGIN) / QUEUE_CHUNK;
+    unsafe {
+        (index(read_volatile(SEND_MAILBOX)) + chunks -
+         index(read_volatile(RECV_MAILBOX))) % chunks
+    }
+}
+
 pub fn enqueue<T, E, F>(f: F) -> Result<T, E>
         where F: FnOnce(&mut [u8]) -> Result<T, E> {
     debug_assert!(!full());
@@ -4,6 +4,10 @@ use csr;
 const INIT: u64 = i64::MAX as u64;
 const FREQ: u64 = csr::CONFIG_CLOCK_FREQUENCY as u64;
 
+// Microseconds between the UNIX epoch and the moment timer0 was started,
+// if wall-clock time is known.
+static mut UTC_OFFSET: Option<u64> = None;
+
 pub fn init() {
     unsafe {
         csr::timer0::en_write(0);
@@ -27,6 +31,18 @@ pub fn get_ms() -> u64 {
     }
 }
 
+pub fn utc_offset_us() -> Option<u64> {
+    unsafe { UTC_OFFSET }
+}
+
+pub fn set_utc_offset_us(offset: Option<u64>) {
+    unsafe { UTC_OFFSET = offset }
+}
+
+pub fn get_utc_us() -> Option<u64> {
+    utc_offset_us().map(|offset| offset + get_us())
+}
+
 pub fn spin_us(interval: u64) {
     unsafe {
         csr::timer0::update_value_write(1);
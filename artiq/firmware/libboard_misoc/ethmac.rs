@@ -10,6 +10,18 @@ const RX_SLOTS: usize = csr::ETHMAC_RX_SLOTS as usize;
 const TX_SLOTS: usize = csr::ETHMAC_TX_SLOTS as usize;
 const SLOT_SIZE: usize = csr::ETHMAC_SLOT_SIZE as usize;
 
+// Counters maintained in software; the gateware only counts errors.
+static mut RX_FRAMES: u64 = 0;
+static mut TX_FRAMES: u64 = 0;
+static mut TX_FULL:   u64 = 0;
+// Whether the last transmit attempt found all TX slots busy, so that a stall is
+// counted once rather than on every poll until a slot frees up.
+static mut TX_STALLED: bool = false;
+// Link state last read from the PHY, and when it was read.
+static mut PHY_LINK_UP:   Option<bool> = None;
+#[cfg(has_ethphy_mdio)]
+static mut PHY_POLLED_AT: u64 = 0;
+
 fn next_rx_slot() -> Option<usize> {
     unsafe {
         if csr::ethmac::sram_writer_ev_pending_read() == 0 {
@@ -65,6 +77,109 @@ impl EthernetDevice {
     }
 }
 
+#[cfg(has_ethphy_mdio)]
+mod mdio {
+    use csr;
+    use clock;
+
+    const MDIO_CLK: u8 = 0x01;
+    const MDIO_OE:  u8 = 0x02;
+    const MDIO_DO:  u8 = 0x04;
+    const MDIO_DI:  u8 = 0x01;
+
+    // Half a period of MDC; 802.3 limits MDC to 2.5 MHz.
+    fn delay() {
+        clock::spin_us(1)
+    }
+
+    fn raw_write(word: u32, bitcount: u32) {
+        let mut word = word << (32 - bitcount);
+        for _ in 0..bitcount {
+            let data = if word & 0x8000_0000 != 0 { MDIO_DO } else { 0 };
+            unsafe {
+                csr::ethphy::mdio_w_write(data | MDIO_OE);
+                delay();
+                csr::ethphy::mdio_w_write(data | MDIO_OE | MDIO_CLK);
+                delay();
+                csr::ethphy::mdio_w_write(data | MDIO_OE);
+            }
+            word <<= 1;
+        }
+    }
+
+    fn raw_read() -> u16 {
+        let mut word = 0;
+        for _ in 0..16 {
+            word <<= 1;
+            unsafe {
+                if csr::ethphy::mdio_r_read() & MDIO_DI != 0 {
+                    word |= 1;
+                }
+                csr::ethphy::mdio_w_write(MDIO_CLK);
+                delay();
+                csr::ethphy::mdio_w_write(0);
+                delay();
+            }
+        }
+        word
+    }
+
+    fn raw_turnaround() {
+        unsafe {
+            delay();
+            csr::ethphy::mdio_w_write(MDIO_CLK);
+            delay();
+            csr::ethphy::mdio_w_write(0);
+            delay();
+            csr::ethphy::mdio_w_write(MDIO_CLK);
+            delay();
+            csr::ethphy::mdio_w_write(0);
+        }
+    }
+
+    pub fn read(phy_addr: u8, reg: u8) -> u16 {
+        unsafe { csr::ethphy::mdio_w_write(MDIO_OE); }
+        raw_write(0xffff_ffff, 32);
+        raw_write(0x0000_0006 | ((phy_addr as u32 & 0x1f) << 7) | ((reg as u32 & 0x1f) << 2), 14);
+        raw_turnaround();
+        let value = raw_read();
+        raw_turnaround();
+        value
+    }
+}
+
+/// Reads the link state from the PHY if it was not read within the last second.
+/// MDIO is slow, so this is called from the network poll loop rather than
+/// whenever the link state is needed.
+pub fn poll_phy_link() {
+    #[cfg(has_ethphy_mdio)]
+    {
+        use clock;
+
+        const PHY_ADDR: u8 = csr::CONFIG_ETHPHY_MDIO_ADDR as u8;
+        const BMSR: u8 = 1;
+        const BMSR_LINK_STATUS: u16 = 1 << 2;
+
+        let now = clock::get_ms();
+        unsafe {
+            if PHY_LINK_UP.is_some() && now < PHY_POLLED_AT + 1000 {
+                return
+            }
+            PHY_POLLED_AT = now;
+            // The link status bit of BMSR latches low, so read it twice to get
+            // the current state.
+            mdio::read(PHY_ADDR, BMSR);
+            PHY_LINK_UP = Some(mdio::read(PHY_ADDR, BMSR) & BMSR_LINK_STATUS != 0)
+        }
+    }
+}
+
+/// Returns whether the PHY reported an established link when last polled, or
+/// `None` if the PHY cannot be queried on this board.
+pub fn phy_link_up() -> Option<bool> {
+    unsafe { PHY_LINK_UP }
+}
+
 impl<'a> Device<'a> for EthernetDevice {
     type RxToken = EthernetRxSlot;
     type TxToken = EthernetTxSlot;
@@ -86,8 +201,15 @@ impl<'a> Device<'a> for EthernetDevice {
 
     fn transmit(&mut self) -> Option<Self::TxToken> {
         if let Some(tx_slot) = next_tx_slot() {
+            unsafe { TX_STALLED = false }
             Some(EthernetTxSlot(tx_slot))
         } else {
+            unsafe {
+                if !TX_STALLED {
+                    TX_STALLED = true;
+                    TX_FULL += 1
+                }
+            }
             None
         }
     }
@@ -103,6 +225,7 @@ impl phy::RxToken for EthernetRxSlot {
             let length = csr::ethmac::sram_writer_length_read() as usize;
             let result = f(slice::from_raw_parts(rx_buffer(self.0), length));
             csr::ethmac::sram_writer_ev_pending_write(1);
+            RX_FRAMES += 1;
             result
         }
     }
@@ -121,6 +244,7 @@ impl phy::TxToken for EthernetTxSlot {
             csr::ethmac::sram_reader_slot_write(self.0 as u8);
             csr::ethmac::sram_reader_length_write(length as u16);
             csr::ethmac::sram_reader_start_write(1);
+            TX_FRAMES += 1;
             Ok(result)
         }
     }
@@ -128,15 +252,22 @@ impl phy::TxToken for EthernetTxSlot {
 
 #[derive(Debug, Clone, PartialEq, Eq, Default)]
 pub struct EthernetStatistics {
-    rx_preamble_errors: u32,
-    rx_crc_errors:      u32,
-    rx_dropped:         u32,
+    pub rx_frames:          u64,
+    pub tx_frames:          u64,
+    /// Number of times transmission stalled because all TX slots were busy.
+    pub tx_full:            u64,
+    pub rx_preamble_errors: u32,
+    pub rx_crc_errors:      u32,
+    pub rx_dropped:         u32,
 }
 
 impl EthernetStatistics {
     pub fn new() -> Self {
         unsafe {
             EthernetStatistics {
+                rx_frames:          RX_FRAMES,
+                tx_frames:          TX_FRAMES,
+                tx_full:            TX_FULL,
                 rx_preamble_errors: csr::ethmac::preamble_errors_read(),
                 rx_crc_errors:      csr::ethmac::crc_errors_read(),
                 rx_dropped:         csr::ethmac::sram_writer_errors_read(),
@@ -144,11 +275,15 @@ impl EthernetStatistics {
         }
     }
 
+    /// Returns the change in error counters since the last call, if any.
     pub fn update(&mut self) -> Option<Self> {
         let old = self.clone();
         *self = Self::new();
 
         let diff = EthernetStatistics {
+            rx_frames:          0,
+            tx_frames:          0,
+            tx_full:            0,
             rx_preamble_errors: self.rx_preamble_errors.wrapping_sub(old.rx_preamble_errors),
             rx_crc_errors:      self.rx_crc_errors.wrapping_sub(old.rx_crc_errors),
             rx_dropped:         self.rx_dropped.wrapping_sub(old.rx_dropped),
@@ -0,0 +1,397 @@
+#![no_std]
+#![feature(alloc)]
+
+//! Decoder for raw DEFLATE streams (RFC 1951), following zlib's puff.c: it trades
+//! speed for size and is only used to unpack kernels uploaded by the host.
+
+extern crate alloc;
+#[cfg(test)]
+extern crate std;
+
+use core::{cmp, fmt};
+use alloc::vec::Vec;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Error {
+    UnexpectedEnd,
+    InvalidBlockType,
+    InvalidStoredLength,
+    InvalidCode,
+    InvalidDistance,
+    SizeMismatch(usize),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::UnexpectedEnd =>
+                write!(f, "unexpected end of input"),
+            Error::InvalidBlockType =>
+                write!(f, "invalid block type"),
+            Error::InvalidStoredLength =>
+                write!(f, "invalid stored block length"),
+            Error::InvalidCode =>
+                write!(f, "invalid Huffman code"),
+            Error::InvalidDistance =>
+                write!(f, "invalid distance"),
+            Error::SizeMismatch(size) =>
+                write!(f, "output does not have the expected size of {} bytes", size),
+        }
+    }
+}
+
+const MAX_BITS: usize = 15;
+const MAX_LCODES: usize = 286;
+const MAX_DCODES: usize = 30;
+const FIX_LCODES: usize = 288;
+
+const LENGTH_BASE: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31,
+    35, 43, 51, 59, 67, 83, 99, 115, 131, 163, 195, 227, 258];
+const LENGTH_EXTRA: [u8; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2,
+    3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0];
+const DIST_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193,
+    257, 385, 513, 769, 1025, 1537, 2049, 3073, 4097, 6145,
+    8193, 12289, 16385, 24577];
+const DIST_EXTRA: [u8; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6,
+    7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13, 13];
+
+// Order in which the code length code lengths are sent.
+const CODE_LENGTH_ORDER: [usize; 19] = [
+    16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15];
+
+// Canonical Huffman code, as the number of codes of each length and the symbols
+// ordered by code.
+struct Huffman {
+    count:  [u16; MAX_BITS + 1],
+    symbol: [u16; FIX_LCODES],
+}
+
+impl Huffman {
+    fn new(lengths: &[u16]) -> Result<Huffman, Error> {
+        let mut huffman = Huffman {
+            count:  [0; MAX_BITS + 1],
+            symbol: [0; FIX_LCODES],
+        };
+        for &length in lengths {
+            huffman.count[length as usize] += 1
+        }
+
+        // Incomplete codes are accepted; decoding a missing code fails.
+        let mut left: i32 = 1;
+        for length in 1..MAX_BITS + 1 {
+            left = (left << 1) - huffman.count[length] as i32;
+            if left < 0 {
+                return Err(Error::InvalidCode)
+            }
+        }
+
+        let mut offsets = [0; MAX_BITS + 1];
+        for length in 1..MAX_BITS {
+            offsets[length + 1] = offsets[length] + huffman.count[length]
+        }
+        for (symbol, &length) in lengths.iter().enumerate() {
+            if length != 0 {
+                huffman.symbol[offsets[length as usize] as usize] = symbol as u16;
+                offsets[length as usize] += 1;
+            }
+        }
+        Ok(huffman)
+    }
+}
+
+struct Inflater<'a> {
+    input:   &'a [u8],
+    pos:     usize,
+    bit_buf: u32,
+    bit_cnt: u32,
+    output:  Vec<u8>,
+    size:    usize,
+}
+
+impl<'a> Inflater<'a> {
+    fn bits(&mut self, need: u32) -> Result<u32, Error> {
+        let mut value = self.bit_buf;
+        while self.bit_cnt < need {
+            let byte = *self.input.get(self.pos).ok_or(Error::UnexpectedEnd)?;
+            self.pos += 1;
+            value |= (byte as u32) << self.bit_cnt;
+            self.bit_cnt += 8;
+        }
+        self.bit_buf = value >> need;
+        self.bit_cnt -= need;
+        Ok(value & ((1 << need) - 1))
+    }
+
+    fn decode(&mut self, huffman: &Huffman) -> Result<u16, Error> {
+        let (mut code, mut first, mut index) = (0i32, 0i32, 0i32);
+        for length in 1..MAX_BITS + 1 {
+            code |= self.bits(1)? as i32;
+            let count = huffman.count[length] as i32;
+            if code - count < first {
+                return Ok(huffman.symbol[(index + (code - first)) as usize])
+            }
+            index += count;
+            first = (first + count) << 1;
+            code <<= 1;
+        }
+        Err(Error::InvalidCode)
+    }
+
+    fn stored(&mut self) -> Result<(), Error> {
+        // Stored blocks start at a byte boundary.
+        self.bit_buf = 0;
+        self.bit_cnt = 0;
+
+        if self.pos + 4 > self.input.len() {
+            return Err(Error::UnexpectedEnd)
+        }
+        let header = &self.input[self.pos..self.pos + 4];
+        let length = header[0] as usize | (header[1] as usize) << 8;
+        let complement = header[2] as usize | (header[3] as usize) << 8;
+        if length != !complement & 0xffff {
+            return Err(Error::InvalidStoredLength)
+        }
+        self.pos += 4;
+
+        if self.pos + length > self.input.len() {
+            return Err(Error::UnexpectedEnd)
+        }
+        if self.output.len() + length > self.size {
+            return Err(Error::SizeMismatch(self.size))
+        }
+        self.output.extend_from_slice(&self.input[self.pos..self.pos + length]);
+        self.pos += length;
+        Ok(())
+    }
+
+    fn codes(&mut self, lengths: &Huffman, distances: &Huffman) -> Result<(), Error> {
+        loop {
+            let symbol = self.decode(lengths)? as usize;
+            if symbol < 256 {
+                if self.output.len() == self.size {
+                    return Err(Error::SizeMismatch(self.size))
+                }
+                self.output.push(symbol as u8)
+            } else if symbol == 256 {
+                return Ok(())
+            } else {
+                let symbol = symbol - 257;
+                if symbol >= LENGTH_BASE.len() {
+                    return Err(Error::InvalidCode)
+                }
+                let length = LENGTH_BASE[symbol] as usize +
+                             self.bits(LENGTH_EXTRA[symbol] as u32)? as usize;
+
+                let symbol = self.decode(distances)? as usize;
+                if symbol >= DIST_BASE.len() {
+                    return Err(Error::InvalidDistance)
+                }
+                let distance = DIST_BASE[symbol] as usize +
+                               self.bits(DIST_EXTRA[symbol] as u32)? as usize;
+                if distance > self.output.len() {
+                    return Err(Error::InvalidDistance)
+                }
+                if self.output.len() + length > self.size {
+                    return Err(Error::SizeMismatch(self.size))
+                }
+
+                // The source may overlap the bytes being written.
+                let start = self.output.len() - distance;
+                for index in start..start + length {
+                    let byte = self.output[index];
+                    self.output.push(byte)
+                }
+            }
+        }
+    }
+
+    fn fixed(&mut self) -> Result<(), Error> {
+        let mut lengths = [0u16; FIX_LCODES];
+        for (symbol, length) in lengths.iter_mut().enumerate() {
+            *length = match symbol {
+                0...143   => 8,
+                144...255 => 9,
+                256...279 => 7,
+                _         => 8
+            }
+        }
+        let lengths = Huffman::new(&lengths)?;
+        let distances = Huffman::new(&[5u16; MAX_DCODES])?;
+        self.codes(&lengths, &distances)
+    }
+
+    fn dynamic(&mut self) -> Result<(), Error> {
+        let nlen  = self.bits(5)? as usize + 257;
+        let ndist = self.bits(5)? as usize + 1;
+        let ncode = self.bits(4)? as usize + 4;
+        if nlen > MAX_LCODES || ndist > MAX_DCODES {
+            return Err(Error::InvalidCode)
+        }
+
+        let mut lengths = [0u16; MAX_LCODES + MAX_DCODES];
+        for &symbol in CODE_LENGTH_ORDER[..ncode].iter() {
+            lengths[symbol] = self.bits(3)? as u16
+        }
+        let code_lengths = Huffman::new(&lengths[..CODE_LENGTH_ORDER.len()])?;
+
+        let mut index = 0;
+        while index < nlen + ndist {
+            let symbol = self.decode(&code_lengths)?;
+            if symbol < 16 {
+                lengths[index] = symbol;
+                index += 1;
+                continue
+            }
+
+            let (length, repeat) = match symbol {
+                16 => {
+                    if index == 0 {
+                        return Err(Error::InvalidCode)
+                    }
+                    (lengths[index - 1], 3 + self.bits(2)? as usize)
+                }
+                17 => (0, 3 + self.bits(3)? as usize),
+                _  => (0, 11 + self.bits(7)? as usize)
+            };
+            if index + repeat > nlen + ndist {
+                return Err(Error::InvalidCode)
+            }
+            for length_ref in lengths[index..index + repeat].iter_mut() {
+                *length_ref = length
+            }
+            index += repeat;
+        }
+
+        // The end-of-block code must be present.
+        if lengths[256] == 0 {
+            return Err(Error::InvalidCode)
+        }
+        let distances = Huffman::new(&lengths[nlen..nlen + ndist])?;
+        let lengths = Huffman::new(&lengths[..nlen])?;
+        self.codes(&lengths, &distances)
+    }
+}
+
+// DEFLATE cannot expand data by more than about 1032 times.
+const MAX_RATIO: usize = 1032;
+
+/// Decompresses a raw DEFLATE stream that must expand to exactly `size` bytes.
+///
+/// `size` is not trusted: the output only grows as the stream is decoded, and
+/// decoding stops as soon as it would exceed `size`.
+pub fn inflate(input: &[u8], size: usize) -> Result<Vec<u8>, Error> {
+    let capacity = cmp::min(size, input.len().saturating_mul(MAX_RATIO));
+    let mut inflater = Inflater {
+        input:   input,
+        pos:     0,
+        bit_buf: 0,
+        bit_cnt: 0,
+        output:  Vec::with_capacity(capacity),
+        size:    size,
+    };
+
+    loop {
+        let last = inflater.bits(1)? == 1;
+        match inflater.bits(2)? {
+            0 => inflater.stored()?,
+            1 => inflater.fixed()?,
+            2 => inflater.dynamic()?,
+            _ => return Err(Error::InvalidBlockType)
+        }
+        if last { break }
+    }
+
+    if inflater.output.len() != size {
+        return Err(Error::SizeMismatch(size))
+    }
+    Ok(inflater.output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLE: &[u8] = include_bytes!("testdata/sample.bin");
+
+    // Streams produced by zlib (raw DEFLATE, wbits -15) from SAMPLE.
+    const COMPRESSED: [(u32, &[u8]); 4] = [
+        (0, include_bytes!("testdata/sample-0.deflate")),
+        (1, include_bytes!("testdata/sample-1.deflate")),
+        (6, include_bytes!("testdata/sample-6.deflate")),
+        (9, include_bytes!("testdata/sample-9.deflate")),
+    ];
+
+    #[test]
+    fn zlib_levels() {
+        for &(level, compressed) in COMPRESSED.iter() {
+            assert_eq!(inflate(compressed, SAMPLE.len()).as_ref().map(|v| &v[..]), Ok(SAMPLE),
+                       "level {}", level)
+        }
+    }
+
+    #[test]
+    fn fixed_huffman() {
+        // "hello, hello, hello kernel" at level 6, a single fixed Huffman block.
+        let compressed = [0xcb, 0x48, 0xcd, 0xc9, 0xc9, 0xd7, 0x51, 0xc8, 0x40,
+                          0xa2, 0x14, 0xb2, 0x53, 0x8b, 0xf2, 0x52, 0x73, 0x00];
+        assert_eq!(compressed[0] & 0b111, 0b011);
+        assert_eq!(inflate(&compressed, 26).as_ref().map(|v| &v[..]),
+                   Ok(&b"hello, hello, hello kernel"[..]))
+    }
+
+    #[test]
+    fn size_mismatch() {
+        for &(level, compressed) in COMPRESSED.iter() {
+            assert_eq!(inflate(compressed, SAMPLE.len() - 1),
+                       Err(Error::SizeMismatch(SAMPLE.len() - 1)), "level {}", level);
+            assert_eq!(inflate(compressed, SAMPLE.len() + 1),
+                       Err(Error::SizeMismatch(SAMPLE.len() + 1)), "level {}", level);
+        }
+    }
+
+    #[test]
+    fn untrusted_size() {
+        // Must fail without trying to reserve the claimed size up front.
+        for &(level, compressed) in COMPRESSED.iter() {
+            assert_eq!(inflate(compressed, usize::max_value()),
+                       Err(Error::SizeMismatch(usize::max_value())), "level {}", level);
+        }
+        assert_eq!(inflate(&[], usize::max_value()), Err(Error::UnexpectedEnd));
+    }
+
+    #[test]
+    fn truncated() {
+        for &(level, compressed) in COMPRESSED.iter() {
+            let ends = compressed.len() - 5..compressed.len();
+            for len in (0..compressed.len()).step_by(5).chain(ends) {
+                assert!(inflate(&compressed[..len], SAMPLE.len()).is_err(),
+                        "level {} truncated to {} bytes", level, len)
+            }
+        }
+    }
+
+    #[test]
+    fn corrupted() {
+        assert_eq!(inflate(&[0b111], 0), Err(Error::InvalidBlockType));
+
+        for &(level, compressed) in COMPRESSED.iter() {
+            let mut corrupted = compressed.to_vec();
+            for pos in (0..compressed.len()).step_by(23) {
+                for &mask in [0x01, 0x10, 0xff].iter() {
+                    corrupted[pos] ^= mask;
+                    // Corruption may go undetected, but must never panic or produce
+                    // output of the wrong size.
+                    if let Ok(output) = inflate(&corrupted, SAMPLE.len()) {
+                        assert_eq!(output.len(), SAMPLE.len(),
+                                   "level {} corrupted at {}", level, pos)
+                    }
+                    corrupted[pos] ^= mask;
+                }
+            }
+        }
+    }
+}
@@ -6,21 +6,84 @@ extern crate log_buffer;
 extern crate board_misoc;
 
 use core::cell::{Cell, RefCell, RefMut};
-use core::fmt::Write;
+use core::fmt::{self, Write};
 use log::{Log, LevelFilter};
 use log_buffer::LogBuffer;
 use board_misoc::clock;
 
+mod syslog;
+
+pub use syslog::{SyslogQueue, Records as SyslogRecords};
+
+/// A point in time formatted as RFC 3339 UTC if wall-clock time is known,
+/// or as seconds since boot otherwise.
+#[derive(Debug, Clone, Copy)]
+pub struct Timestamp {
+    boot_us: u64,
+    utc_us:  Option<u64>
+}
+
+impl Timestamp {
+    pub fn now() -> Timestamp {
+        let boot_us = clock::get_us();
+        Timestamp {
+            boot_us: boot_us,
+            utc_us:  clock::utc_offset_us().map(|offset| offset + boot_us)
+        }
+    }
+
+    pub fn utc(&self) -> Option<UtcTimestamp> {
+        self.utc_us.map(UtcTimestamp)
+    }
+}
+
+impl fmt::Display for Timestamp {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.utc() {
+            Some(utc) => write!(f, "{}", utc),
+            None => write!(f, "{:6}.{:06}s", self.boot_us / 1_000_000, self.boot_us % 1_000_000)
+        }
+    }
+}
+
+/// Microseconds since the UNIX epoch, formatted as RFC 3339.
+#[derive(Debug, Clone, Copy)]
+pub struct UtcTimestamp(pub u64);
+
+impl fmt::Display for UtcTimestamp {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let seconds = self.0 / 1_000_000;
+        let micros  = self.0 % 1_000_000;
+
+        // See http://howardhinnant.github.io/date_algorithms.html#civil_from_days.
+        let days = seconds / 86400 + 719468;
+        let era  = days / 146097;
+        let doe  = days - era * 146097;
+        let yoe  = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+        let doy  = doe - (365 * yoe + yoe / 4 - yoe / 100);
+        let mp   = (5 * doy + 2) / 153;
+        let day  = doy - (153 * mp + 2) / 5 + 1;
+        let month = if mp < 10 { mp + 3 } else { mp - 9 };
+        let year  = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+
+        write!(f, "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:06}Z",
+               year, month, day,
+               seconds % 86400 / 3600, seconds % 3600 / 60, seconds % 60, micros)
+    }
+}
+
 pub struct LogBufferRef<'a> {
     buffer:        RefMut<'a, LogBuffer<&'static mut [u8]>>,
-    old_log_level: LevelFilter
+    old_log_level: LevelFilter,
+    notify:        Option<fn()>
 }
 
 impl<'a> LogBufferRef<'a> {
-    fn new(buffer: RefMut<'a, LogBuffer<&'static mut [u8]>>) -> LogBufferRef<'a> {
+    fn new(buffer: RefMut<'a, LogBuffer<&'static mut [u8]>>,
+           notify: Option<fn()>) -> LogBufferRef<'a> {
         let old_log_level = log::max_level();
         log::set_max_level(LevelFilter::Off);
-        LogBufferRef { buffer, old_log_level }
+        LogBufferRef { buffer, old_log_level, notify }
     }
 
     pub fn is_empty(&self) -> bool {
@@ -38,13 +101,23 @@ impl<'a> LogBufferRef<'a> {
 
 impl<'a> Drop for LogBufferRef<'a> {
     fn drop(&mut self) {
-        log::set_max_level(self.old_log_level)
+        log::set_max_level(self.old_log_level);
+        if let Some(notify) = self.notify {
+            notify()
+        }
     }
 }
 
 pub struct BufferLogger {
-    buffer:      RefCell<LogBuffer<&'static mut [u8]>>,
-    uart_filter: Cell<LevelFilter>
+    buffer:        RefCell<LogBuffer<&'static mut [u8]>>,
+    uart_filter:   Cell<LevelFilter>,
+    // Called whenever a record has been written to the buffer, and whenever
+    // the buffer is released.
+    buffer_notify: Cell<Option<fn()>>,
+    syslog:        RefCell<Option<SyslogQueue>>,
+    syslog_filter: Cell<LevelFilter>,
+    // Records that could not be queued because the queue was borrowed.
+    syslog_missed: Cell<u32>,
 }
 
 static mut LOGGER: *const BufferLogger = 0 as *const _;
@@ -54,6 +127,10 @@ impl BufferLogger {
         BufferLogger {
             buffer: RefCell::new(LogBuffer::new(buffer)),
             uart_filter: Cell::new(LevelFilter::Info),
+            buffer_notify: Cell::new(None),
+            syslog: RefCell::new(None),
+            syslog_filter: Cell::new(LevelFilter::Off),
+            syslog_missed: Cell::new(0),
         }
     }
 
@@ -72,12 +149,26 @@ impl BufferLogger {
     }
 
     pub fn buffer<'a>(&'a self) -> Result<LogBufferRef<'a>, ()> {
+        let notify = self.buffer_notify.get();
         self.buffer
             .try_borrow_mut()
-            .map(LogBufferRef::new)
+            .map(|buffer| LogBufferRef::new(buffer, notify))
             .map_err(|_| ())
     }
 
+    /// Returns whether the buffer holds any records and is not borrowed. Unlike
+    /// `buffer`, this does not call the buffer notifier.
+    pub fn has_records(&self) -> bool {
+        self.buffer
+            .try_borrow()
+            .map(|buffer| !buffer.is_empty())
+            .unwrap_or(false)
+    }
+
+    pub fn set_buffer_notify(&self, notify: fn()) {
+        self.buffer_notify.set(Some(notify))
+    }
+
     pub fn uart_log_level(&self) -> LevelFilter {
         self.uart_filter.get()
     }
@@ -85,6 +176,32 @@ impl BufferLogger {
     pub fn set_uart_log_level(&self, max_level: LevelFilter) {
         self.uart_filter.set(max_level)
     }
+
+    /// Starts queueing records at `max_level` and above for a syslog server;
+    /// `notify` is called whenever a record has been queued.
+    pub fn enable_syslog(&self, storage: &'static mut [u8], hostname: &str,
+                         max_level: LevelFilter, notify: fn()) {
+        *self.syslog.borrow_mut() = Some(SyslogQueue::new(storage, hostname, notify));
+        self.syslog_filter.set(max_level)
+    }
+
+    pub fn syslog_log_level(&self) -> LevelFilter {
+        self.syslog_filter.get()
+    }
+
+    pub fn set_syslog_log_level(&self, max_level: LevelFilter) {
+        self.syslog_filter.set(max_level)
+    }
+
+    pub fn syslog<'a>(&'a self) -> Result<RefMut<'a, SyslogQueue>, ()> {
+        let queue = self.syslog.try_borrow_mut().map_err(|_| ())?;
+        if queue.is_none() {
+            return Err(())
+        }
+        let mut queue = RefMut::map(queue, |queue| queue.as_mut().unwrap());
+        queue.add_suppressed(self.syslog_missed.replace(0));
+        Ok(queue)
+    }
 }
 
 // required for impl Log
@@ -97,19 +214,36 @@ impl Log for BufferLogger {
 
     fn log(&self, record: &log::Record) {
         if self.enabled(record.metadata()) {
-            let timestamp = clock::get_us();
-            let seconds   = timestamp / 1_000_000;
-            let micros    = timestamp % 1_000_000;
+            let timestamp = Timestamp::now();
 
             if let Ok(mut buffer) = self.buffer.try_borrow_mut() {
-                writeln!(buffer, "[{:6}.{:06}s] {:>5}({}): {}", seconds, micros,
+                writeln!(buffer, "[{}] {:>5}({}): {}", timestamp,
                          record.level(), record.target(), record.args()).unwrap();
+                if let Some(notify) = self.buffer_notify.get() {
+                    notify()
+                }
             }
 
             if record.level() <= self.uart_filter.get() {
-                println!("[{:6}.{:06}s] {:>5}({}): {}", seconds, micros,
+                println!("[{}] {:>5}({}): {}", timestamp,
                          record.level(), record.target(), record.args());
             }
+
+            if record.level() <= self.syslog_filter.get() {
+                match self.syslog.try_borrow_mut() {
+                    Ok(mut syslog) => {
+                        if let Some(ref mut syslog) = *syslog {
+                            syslog.add_suppressed(self.syslog_missed.replace(0));
+                            syslog.push(timestamp, record)
+                        }
+                    }
+                    // Logged from an interrupt or while the queue is being flushed;
+                    // report it together with the rate limited records.
+                    Err(_) => if SyslogQueue::forwards(record) {
+                        self.syslog_missed.set(self.syslog_missed.get().saturating_add(1))
+                    }
+                }
+            }
         }
     }
 
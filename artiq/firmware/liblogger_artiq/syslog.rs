@@ -0,0 +1,210 @@
+use core::{cmp, str, fmt};
+use core::fmt::Write;
+use log::{self, Level};
+use board_misoc::clock;
+
+use Timestamp;
+
+// RFC 5424 facility "local0".
+const FACILITY: u8 = 16;
+
+// Records are dropped once the token bucket is empty; it holds at most BURST tokens
+// and regains one token every REFILL_MS milliseconds.
+const BURST:     u32 = 32;
+const REFILL_MS: u64 = 100;
+
+// Longest record, including the RFC 5424 header, that is put into the queue.
+const MAX_RECORD_SIZE: usize = 480;
+
+const MAX_HOSTNAME_SIZE: usize = 64;
+
+struct SliceWriter<'a> {
+    buf:    &'a mut [u8],
+    offset: usize,
+}
+
+impl<'a> fmt::Write for SliceWriter<'a> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        // Truncate rather than fail; a partial record is more useful than none.
+        let length = cmp::min(s.len(), self.buf.len() - self.offset);
+        self.buf[self.offset..self.offset + length].copy_from_slice(&s.as_bytes()[..length]);
+        self.offset += length;
+        Ok(())
+    }
+}
+
+fn severity(level: Level) -> u8 {
+    match level {
+        Level::Error => 3,
+        Level::Warn  => 4,
+        Level::Info  => 6,
+        Level::Debug => 7,
+        Level::Trace => 7,
+    }
+}
+
+/// A queue of RFC 5424 formatted records waiting to be sent to a syslog server.
+///
+/// Each record is stored as a big-endian 16-bit length followed by the datagram payload.
+pub struct SyslogQueue {
+    storage:      &'static mut [u8],
+    length:       usize,
+    hostname:     [u8; MAX_HOSTNAME_SIZE],
+    hostname_len: usize,
+    tokens:       u32,
+    refilled_at:  u64,
+    suppressed:   u32,
+    // Called whenever a record has been queued.
+    notify:       fn(),
+}
+
+impl SyslogQueue {
+    pub(crate) fn new(storage: &'static mut [u8], hostname: &str, notify: fn()) -> SyslogQueue {
+        let mut queue = SyslogQueue {
+            storage:      storage,
+            length:       0,
+            hostname:     [0; MAX_HOSTNAME_SIZE],
+            hostname_len: 0,
+            tokens:       BURST,
+            refilled_at:  clock::get_ms(),
+            suppressed:   0,
+            notify:       notify,
+        };
+        // RFC 5424 hostnames are printable ASCII without spaces.
+        for &byte in hostname.as_bytes().iter()
+                .filter(|&&byte| byte > 32 && byte < 127)
+                .take(MAX_HOSTNAME_SIZE) {
+            queue.hostname[queue.hostname_len] = byte;
+            queue.hostname_len += 1;
+        }
+        if queue.hostname_len == 0 {
+            queue.hostname[0] = b'-';
+            queue.hostname_len = 1;
+        }
+        queue
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.length == 0
+    }
+
+    pub fn clear(&mut self) {
+        self.length = 0
+    }
+
+    pub fn records<'a>(&'a self) -> Records<'a> {
+        Records { data: &self.storage[..self.length] }
+    }
+
+    /// Returns the number of records dropped by rate limiting, due to a full queue
+    /// or while the queue was borrowed that have not been reported yet.
+    pub fn suppressed(&self) -> u32 {
+        self.suppressed
+    }
+
+    pub(crate) fn add_suppressed(&mut self, count: u32) {
+        self.suppressed = self.suppressed.saturating_add(count)
+    }
+
+    /// Returns whether `record` is meant to be forwarded at all.
+    pub(crate) fn forwards(record: &log::Record) -> bool {
+        // Sending a datagram emits smoltcp log records; forwarding those would
+        // feed back into the queue forever.
+        !record.target().starts_with("smoltcp")
+    }
+
+    fn take_token(&mut self) -> bool {
+        let now = clock::get_ms();
+        let refill = (now - self.refilled_at) / REFILL_MS;
+        if refill > 0 {
+            self.tokens = cmp::min(BURST as u64, self.tokens as u64 + refill) as u32;
+            self.refilled_at += refill * REFILL_MS;
+        }
+
+        if self.tokens > 0 {
+            self.tokens -= 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn append(&mut self, timestamp: Timestamp, level: Level, target: &str,
+              args: fmt::Arguments) -> bool {
+        if self.storage.len() - self.length < 2 + MAX_RECORD_SIZE {
+            return false
+        }
+
+        let hostname = str::from_utf8(&self.hostname[..self.hostname_len]).unwrap_or("-");
+        // MSGID is limited to 32 characters; cut at a character boundary.
+        let mut msgid_len = cmp::min(target.len(), 32);
+        while !target.is_char_boundary(msgid_len) {
+            msgid_len -= 1
+        }
+        let msgid = &target[..msgid_len];
+
+        let start = self.length + 2;
+        let written = {
+            let mut writer = SliceWriter {
+                buf:    &mut self.storage[start..start + MAX_RECORD_SIZE],
+                offset: 0
+            };
+            let _ = write!(writer, "<{}>1 ", FACILITY * 8 + severity(level));
+            let _ = match timestamp.utc() {
+                Some(utc) => write!(writer, "{}", utc),
+                None => write!(writer, "-")
+            };
+            let _ = write!(writer, " {} artiq - {} - {}", hostname, msgid, args);
+            writer.offset
+        };
+
+        self.storage[self.length]     = (written >> 8) as u8;
+        self.storage[self.length + 1] = written as u8;
+        self.length = start + written;
+        true
+    }
+
+    pub(crate) fn push(&mut self, timestamp: Timestamp, record: &log::Record) {
+        if !SyslogQueue::forwards(record) {
+            return
+        }
+
+        if !self.take_token() {
+            self.suppressed = self.suppressed.saturating_add(1);
+            return
+        }
+
+        if self.suppressed > 0 {
+            let suppressed = self.suppressed;
+            if self.append(timestamp, Level::Warn, "logger_artiq",
+                           format_args!("{} log records suppressed", suppressed)) {
+                self.suppressed = 0
+            }
+        }
+
+        if self.append(timestamp, record.level(), record.target(), *record.args()) {
+            (self.notify)()
+        } else {
+            self.suppressed = self.suppressed.saturating_add(1)
+        }
+    }
+}
+
+pub struct Records<'a> {
+    data: &'a [u8]
+}
+
+impl<'a> Iterator for Records<'a> {
+    type Item = &'a [u8];
+
+    fn next(&mut self) -> Option<&'a [u8]> {
+        if self.data.len() < 2 {
+            return None
+        }
+
+        let length = ((self.data[0] as usize) << 8) | self.data[1] as usize;
+        let (record, rest) = self.data[2..].split_at(length);
+        self.data = rest;
+        Some(record)
+    }
+}
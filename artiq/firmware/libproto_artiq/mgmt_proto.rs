@@ -34,6 +34,9 @@ impl<T> From<ReadStringError<IoError<T>>> for Error<T> {
     }
 }
 
+/// Version of the layout of `Reply::Profile` and the profile data following it.
+pub const PROFILE_VERSION: u8 = 2;
+
 pub fn read_magic<R>(reader: &mut R) -> Result<(), Error<R::ReadError>>
     where R: Read + ?Sized
 {
@@ -70,11 +73,26 @@ pub enum Request {
     },
     StopProfiler,
     GetProfile,
+    StreamProfile {
+        interval_us: u32,
+        window_ms: u32,
+        hits_size: u32,
+        edges_size: u32,
+    },
 
     Hotswap(Vec<u8>),
     Reboot,
 
     DebugAllocator,
+    CheckHeap,
+
+    GetClockMapping,
+    GetEthernetStatistics,
+
+    GetThreads,
+
+    ConfigurePerfCounters { kernel_only: bool, counters: Vec<u32> },
+    ReadPerfCounters,
 }
 
 pub enum Reply<'a> {
@@ -86,9 +104,46 @@ pub enum Reply<'a> {
 
     ConfigData(&'a [u8]),
 
-    Profile,
+    Profile {
+        interval_us: u32,
+        samples:     u64,
+        dropped:     u64,
+        duration_us: u64,
+        ident:       &'a str,
+    },
 
     RebootImminent,
+
+    ClockMapping {
+        utc_us:       Option<u64>,
+        clock_us:     u64,
+        rtio_counter: u64,
+    },
+
+    EthernetStatistics {
+        rx_frames:          u64,
+        tx_frames:          u64,
+        tx_full:            u64,
+        rx_preamble_errors: u32,
+        rx_crc_errors:      u32,
+        rx_dropped:         u32,
+        phy_link_up:        Option<bool>,
+    },
+
+    ThreadList,
+
+    HeapStatistics {
+        busy:          u32,
+        idle:          u32,
+        meta:          u32,
+        busy_blocks:   u32,
+        idle_blocks:   u32,
+        largest_idle:  u32,
+        fragmentation: u32,
+    },
+    HeapCorruption(&'a str),
+
+    PerfCounters { kernel_only: bool },
 }
 
 impl Request {
@@ -137,11 +192,36 @@ impl Request {
             },
             10 => Request::StopProfiler,
             11 => Request::GetProfile,
+            20 => Request::StreamProfile {
+                interval_us: reader.read_u32()?,
+                window_ms: reader.read_u32()?,
+                hits_size: reader.read_u32()?,
+                edges_size: reader.read_u32()?,
+            },
 
             4 => Request::Hotswap(reader.read_bytes()?),
             5 => Request::Reboot,
 
             8 => Request::DebugAllocator,
+            19 => Request::CheckHeap,
+
+            16 => Request::GetClockMapping,
+            17 => Request::GetEthernetStatistics,
+
+            18 => Request::GetThreads,
+
+            21 => {
+                let kernel_only = reader.read_bool()?;
+                let mut counters = Vec::new();
+                for _ in 0..reader.read_u32()? {
+                    counters.push(reader.read_u32()?)
+                }
+                Request::ConfigurePerfCounters {
+                    kernel_only: kernel_only,
+                    counters: counters
+                }
+            }
+            22 => Request::ReadPerfCounters,
 
             ty => return Err(Error::UnknownPacket(ty))
         })
@@ -174,14 +254,71 @@ impl<'a> Reply<'a> {
                 writer.write_bytes(bytes)?;
             },
 
-            Reply::Profile => {
+            Reply::Profile { interval_us, samples, dropped, duration_us, ident } => {
                 writer.write_u8(5)?;
+                writer.write_u8(PROFILE_VERSION)?;
+                writer.write_u32(interval_us)?;
+                writer.write_u64(samples)?;
+                writer.write_u64(dropped)?;
+                writer.write_u64(duration_us)?;
+                writer.write_string(ident)?;
                 // profile data follows
             }
 
             Reply::RebootImminent => {
                 writer.write_u8(3)?;
             }
+
+            Reply::ClockMapping { utc_us, clock_us, rtio_counter } => {
+                writer.write_u8(8)?;
+                writer.write_bool(utc_us.is_some())?;
+                writer.write_u64(utc_us.unwrap_or(0))?;
+                writer.write_u64(clock_us)?;
+                writer.write_u64(rtio_counter)?;
+            }
+
+            Reply::EthernetStatistics {
+                rx_frames, tx_frames, tx_full,
+                rx_preamble_errors, rx_crc_errors, rx_dropped,
+                phy_link_up
+            } => {
+                writer.write_u8(9)?;
+                writer.write_u64(rx_frames)?;
+                writer.write_u64(tx_frames)?;
+                writer.write_u64(tx_full)?;
+                writer.write_u32(rx_preamble_errors)?;
+                writer.write_u32(rx_crc_errors)?;
+                writer.write_u32(rx_dropped)?;
+                writer.write_bool(phy_link_up.is_some())?;
+                writer.write_bool(phy_link_up.unwrap_or(false))?;
+            }
+
+            Reply::ThreadList => {
+                writer.write_u8(10)?;
+            }
+
+            Reply::HeapStatistics {
+                busy, idle, meta, busy_blocks, idle_blocks, largest_idle, fragmentation
+            } => {
+                writer.write_u8(11)?;
+                writer.write_u32(busy)?;
+                writer.write_u32(idle)?;
+                writer.write_u32(meta)?;
+                writer.write_u32(busy_blocks)?;
+                writer.write_u32(idle_blocks)?;
+                writer.write_u32(largest_idle)?;
+                writer.write_u32(fragmentation)?;
+            }
+            Reply::HeapCorruption(ref description) => {
+                writer.write_u8(12)?;
+                writer.write_string(description)?;
+            }
+
+            Reply::PerfCounters { kernel_only } => {
+                writer.write_u8(13)?;
+                writer.write_bool(kernel_only)?;
+                // counters follow
+            }
         }
         Ok(())
     }
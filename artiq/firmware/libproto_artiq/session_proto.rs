@@ -61,12 +61,103 @@ fn write_sync<W>(writer: &mut W) -> Result<(), IoError<W::WriteError>>
     writer.write_all(&[0x5a; 4])
 }
 
+/// Revision of the session protocol, sent with the capabilities.
+pub const PROTOCOL_VERSION: u8 = 8;
+
+/// Optional features of the firmware build, reported in `Reply::SystemInfo`.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Capabilities {
+    pub rtio_dma:      bool,
+    pub drtio:         bool,
+    pub rtio_analyzer: bool,
+    pub moninj:        bool,
+    pub grabber:       bool,
+    pub kernel_cache:  bool,
+    pub kernel_deflate: bool,
+    pub kernel_abort:  bool,
+    pub kernel_staging: bool,
+    pub run_stats:     bool,
+    pub kernel_log:    bool,
+    pub converter_spi: bool,
+    pub i2c_buses:     u8,
+    pub drtio_links:   u8,
+}
+
+impl Capabilities {
+    fn flags(&self) -> u32 {
+        (self.rtio_dma as u32)           |
+        (self.drtio as u32)         << 1 |
+        (self.rtio_analyzer as u32) << 2 |
+        (self.moninj as u32)        << 3 |
+        (self.grabber as u32)       << 4 |
+        (self.kernel_cache as u32)  << 5 |
+        (self.kernel_deflate as u32) << 6 |
+        (self.kernel_abort as u32)  << 7 |
+        (self.kernel_staging as u32) << 8 |
+        (self.run_stats as u32)     << 9 |
+        (self.kernel_log as u32)    << 10 |
+        (self.converter_spi as u32) << 11
+    }
+
+    fn write_to<W>(&self, writer: &mut W) -> Result<(), IoError<W::WriteError>>
+        where W: Write + ?Sized
+    {
+        writer.write_u8(PROTOCOL_VERSION)?;
+        writer.write_u32(self.flags())?;
+        writer.write_u8(self.i2c_buses)?;
+        writer.write_u8(self.drtio_links)?;
+        Ok(())
+    }
+}
+
+/// Statistics of a kernel run, reported in `Reply::RunStats`.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RunStats {
+    pub load_us:            u64,
+    pub run_us:             u64,
+    pub sync_rpcs:          u32,
+    pub async_rpcs:         u32,
+    pub rpc_bytes_sent:     u64,
+    pub rpc_bytes_received: u64,
+    pub dma_recorded:       u32,
+    pub dma_played:         u32,
+    pub cache_gets:         u32,
+    pub cache_puts:         u32,
+    pub rpc_queue_peak:     u32,
+}
+
+impl RunStats {
+    fn write_to<W>(&self, writer: &mut W) -> Result<(), IoError<W::WriteError>>
+        where W: Write + ?Sized
+    {
+        writer.write_u64(self.load_us)?;
+        writer.write_u64(self.run_us)?;
+        writer.write_u32(self.sync_rpcs)?;
+        writer.write_u32(self.async_rpcs)?;
+        writer.write_u64(self.rpc_bytes_sent)?;
+        writer.write_u64(self.rpc_bytes_received)?;
+        writer.write_u32(self.dma_recorded)?;
+        writer.write_u32(self.dma_played)?;
+        writer.write_u32(self.cache_gets)?;
+        writer.write_u32(self.cache_puts)?;
+        writer.write_u32(self.rpc_queue_peak)?;
+        Ok(())
+    }
+}
+
 #[derive(Debug)]
 pub enum Request {
     SystemInfo,
 
     LoadKernel(Vec<u8>),
+    LoadKernelByHash([u8; 32]),
+    LoadAndCacheKernel { hash: [u8; 32], kernel: Vec<u8> },
+    LoadCompressedKernel { hash: [u8; 32], size: u32, kernel: Vec<u8> },
+    StageKernel(Vec<u8>),
     RunKernel,
+    AbortKernel,
+    GetRunStats,
+    ForwardKernelLog(bool),
 
     RpcReply { tag: Vec<u8> },
     RpcException {
@@ -84,14 +175,19 @@ pub enum Request {
 pub enum Reply<'a> {
     SystemInfo {
         ident: &'a str,
-        finished_cleanly: bool
+        finished_cleanly: bool,
+        capabilities: Capabilities
     },
 
     LoadCompleted,
     LoadFailed(&'a str),
+    KernelNotCached,
+    StagedKernelLoaded { load_us: u64 },
 
     KernelFinished,
     KernelStartupFailed,
+    KernelAborted,
+    RunStats(Option<RunStats>),
     KernelException {
         name:      &'a str,
         message:   &'a str,
@@ -105,6 +201,8 @@ pub enum Reply<'a> {
 
     RpcRequest { async: bool },
 
+    KernelLog { run_id: u32, message: &'a str },
+
     WatchdogExpired,
     ClockFailure,
 }
@@ -118,7 +216,33 @@ impl Request {
             3  => Request::SystemInfo,
 
             5  => Request::LoadKernel(reader.read_bytes()?),
+            9  => {
+                let mut hash = [0; 32];
+                reader.read_exact(&mut hash)?;
+                Request::LoadKernelByHash(hash)
+            }
+            10 => {
+                let mut hash = [0; 32];
+                reader.read_exact(&mut hash)?;
+                Request::LoadAndCacheKernel {
+                    hash:   hash,
+                    kernel: reader.read_bytes()?
+                }
+            }
+            11 => {
+                let mut hash = [0; 32];
+                reader.read_exact(&mut hash)?;
+                Request::LoadCompressedKernel {
+                    hash:   hash,
+                    size:   reader.read_u32()?,
+                    kernel: reader.read_bytes()?
+                }
+            }
+            13 => Request::StageKernel(reader.read_bytes()?),
             6  => Request::RunKernel,
+            12 => Request::AbortKernel,
+            14 => Request::GetRunStats,
+            15 => Request::ForwardKernelLog(reader.read_bool()?),
 
             7  => Request::RpcReply {
                 tag: reader.read_bytes()?
@@ -146,11 +270,14 @@ impl<'a> Reply<'a> {
     {
         write_sync(writer)?;
         match *self {
-            Reply::SystemInfo { ident, finished_cleanly } => {
-                writer.write_u8(2)?;
+            Reply::SystemInfo { ident, finished_cleanly, ref capabilities } => {
+                // Firmware without the capabilities block replies with type 2;
+                // the new type lets hosts tell whether the block follows.
+                writer.write_u8(21)?;
                 writer.write(b"AROR")?;
                 writer.write_string(ident)?;
                 writer.write_u8(finished_cleanly as u8)?;
+                capabilities.write_to(writer)?;
             },
 
             Reply::LoadCompleted => {
@@ -160,6 +287,13 @@ impl<'a> Reply<'a> {
                 writer.write_u8(6)?;
                 writer.write_string(reason)?;
             },
+            Reply::KernelNotCached => {
+                writer.write_u8(16)?;
+            },
+            Reply::StagedKernelLoaded { load_us } => {
+                writer.write_u8(18)?;
+                writer.write_u64(load_us)?;
+            },
 
             Reply::KernelFinished => {
                 writer.write_u8(7)?;
@@ -167,6 +301,19 @@ impl<'a> Reply<'a> {
             Reply::KernelStartupFailed => {
                 writer.write_u8(8)?;
             },
+            Reply::KernelAborted => {
+                writer.write_u8(17)?;
+            },
+            Reply::RunStats(ref stats) => {
+                writer.write_u8(19)?;
+                match *stats {
+                    None => writer.write_u8(0)?,
+                    Some(ref stats) => {
+                        writer.write_u8(1)?;
+                        stats.write_to(writer)?;
+                    }
+                }
+            },
             Reply::KernelException {
                 name, message, param, file, line, column, function, backtrace
             } => {
@@ -191,6 +338,12 @@ impl<'a> Reply<'a> {
                 writer.write_u8(async as u8)?;
             },
 
+            Reply::KernelLog { run_id, message } => {
+                writer.write_u8(20)?;
+                writer.write_u32(run_id)?;
+                writer.write_string(message)?;
+            },
+
             Reply::WatchdogExpired => {
                 writer.write_u8(14)?;
             },
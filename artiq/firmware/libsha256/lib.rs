@@ -0,0 +1,191 @@
+#![no_std]
+
+//! SHA-256 (FIPS 180-4), used to check that a kernel uploaded for caching matches
+//! the hash the host announced for it.
+
+#[cfg(test)]
+#[macro_use]
+extern crate std;
+
+const K: [u32; 64] = [
+    0x428a2f98, 0x71374491, 0xb5c0fbcf, 0xe9b5dba5, 0x3956c25b, 0x59f111f1, 0x923f82a4, 0xab1c5ed5,
+    0xd807aa98, 0x12835b01, 0x243185be, 0x550c7dc3, 0x72be5d74, 0x80deb1fe, 0x9bdc06a7, 0xc19bf174,
+    0xe49b69c1, 0xefbe4786, 0x0fc19dc6, 0x240ca1cc, 0x2de92c6f, 0x4a7484aa, 0x5cb0a9dc, 0x76f988da,
+    0x983e5152, 0xa831c66d, 0xb00327c8, 0xbf597fc7, 0xc6e00bf3, 0xd5a79147, 0x06ca6351, 0x14292967,
+    0x27b70a85, 0x2e1b2138, 0x4d2c6dfc, 0x53380d13, 0x650a7354, 0x766a0abb, 0x81c2c92e, 0x92722c85,
+    0xa2bfe8a1, 0xa81a664b, 0xc24b8b70, 0xc76c51a3, 0xd192e819, 0xd6990624, 0xf40e3585, 0x106aa070,
+    0x19a4c116, 0x1e376c08, 0x2748774c, 0x34b0bcb5, 0x391c0cb3, 0x4ed8aa4a, 0x5b9cca4f, 0x682e6ff3,
+    0x748f82ee, 0x78a5636f, 0x84c87814, 0x8cc70208, 0x90befffa, 0xa4506ceb, 0xbef9a3f7, 0xc67178f2,
+];
+
+const H0: [u32; 8] = [
+    0x6a09e667, 0xbb67ae85, 0x3c6ef372, 0xa54ff53a, 0x510e527f, 0x9b05688c, 0x1f83d9ab, 0x5be0cd19,
+];
+
+pub type Digest = [u8; 32];
+
+#[derive(Debug, Clone)]
+pub struct Sha256 {
+    state:    [u32; 8],
+    block:    [u8; 64],
+    buffered: usize,
+    // Length of the message so far, in bytes.
+    length:   u64,
+}
+
+impl Sha256 {
+    pub fn new() -> Sha256 {
+        Sha256 {
+            state:    H0,
+            block:    [0; 64],
+            buffered: 0,
+            length:   0,
+        }
+    }
+
+    pub fn update(&mut self, mut data: &[u8]) {
+        self.length += data.len() as u64;
+
+        if self.buffered > 0 {
+            let length = core::cmp::min(data.len(), 64 - self.buffered);
+            self.block[self.buffered..self.buffered + length].copy_from_slice(&data[..length]);
+            self.buffered += length;
+            data = &data[length..];
+            if self.buffered < 64 {
+                return
+            }
+            let block = self.block;
+            self.compress(&block);
+            self.buffered = 0;
+        }
+
+        while data.len() >= 64 {
+            self.compress(&data[..64]);
+            data = &data[64..];
+        }
+
+        self.block[..data.len()].copy_from_slice(data);
+        self.buffered = data.len();
+    }
+
+    pub fn finish(mut self) -> Digest {
+        let bit_length = self.length * 8;
+
+        // A one bit, zeros up to 8 bytes short of a block boundary, then the length.
+        let mut padding = [0; 72];
+        padding[0] = 0x80;
+        let padding_length = if self.buffered < 56 { 56 - self.buffered }
+                             else { 120 - self.buffered };
+        for (index, byte) in padding[padding_length..padding_length + 8].iter_mut().enumerate() {
+            *byte = (bit_length >> (56 - 8 * index)) as u8;
+        }
+        let length = self.length;
+        self.update(&padding[..padding_length + 8]);
+        debug_assert!(self.buffered == 0);
+        self.length = length;
+
+        let mut digest = [0; 32];
+        for (bytes, word) in digest.chunks_mut(4).zip(self.state.iter()) {
+            bytes[0] = (word >> 24) as u8;
+            bytes[1] = (word >> 16) as u8;
+            bytes[2] = (word >>  8) as u8;
+            bytes[3] =  *word       as u8;
+        }
+        digest
+    }
+
+    fn compress(&mut self, block: &[u8]) {
+        let mut w = [0u32; 64];
+        for (word, bytes) in w.iter_mut().zip(block.chunks(4)) {
+            *word = (bytes[0] as u32) << 24 | (bytes[1] as u32) << 16 |
+                    (bytes[2] as u32) <<  8 |  bytes[3] as u32;
+        }
+        for i in 16..64 {
+            let s0 = w[i - 15].rotate_right(7) ^ w[i - 15].rotate_right(18) ^ (w[i - 15] >> 3);
+            let s1 = w[i - 2].rotate_right(17) ^ w[i - 2].rotate_right(19) ^ (w[i - 2] >> 10);
+            w[i] = w[i - 16].wrapping_add(s0).wrapping_add(w[i - 7]).wrapping_add(s1);
+        }
+
+        let (mut a, mut b, mut c, mut d) =
+            (self.state[0], self.state[1], self.state[2], self.state[3]);
+        let (mut e, mut f, mut g, mut h) =
+            (self.state[4], self.state[5], self.state[6], self.state[7]);
+        for i in 0..64 {
+            let s1  = e.rotate_right(6) ^ e.rotate_right(11) ^ e.rotate_right(25);
+            let ch  = (e & f) ^ (!e & g);
+            let t1  = h.wrapping_add(s1).wrapping_add(ch).wrapping_add(K[i]).wrapping_add(w[i]);
+            let s0  = a.rotate_right(2) ^ a.rotate_right(13) ^ a.rotate_right(22);
+            let maj = (a & b) ^ (a & c) ^ (b & c);
+            let t2  = s0.wrapping_add(maj);
+            h = g;
+            g = f;
+            f = e;
+            e = d.wrapping_add(t1);
+            d = c;
+            c = b;
+            b = a;
+            a = t1.wrapping_add(t2);
+        }
+
+        for (state, value) in self.state.iter_mut().zip([a, b, c, d, e, f, g, h].iter()) {
+            *state = state.wrapping_add(*value)
+        }
+    }
+}
+
+impl Default for Sha256 {
+    fn default() -> Sha256 {
+        Sha256::new()
+    }
+}
+
+/// Returns the SHA-256 digest of `data`.
+pub fn digest(data: &[u8]) -> Digest {
+    let mut hasher = Sha256::new();
+    hasher.update(data);
+    hasher.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::vec::Vec;
+    use std::string::String;
+    use std::fmt::Write;
+
+    fn hex(digest: &Digest) -> String {
+        let mut s = String::new();
+        for byte in digest.iter() {
+            write!(s, "{:02x}", byte).unwrap();
+        }
+        s
+    }
+
+    #[test]
+    fn fips_vectors() {
+        assert_eq!(hex(&digest(b"")),
+                   "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855");
+        assert_eq!(hex(&digest(b"abc")),
+                   "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad");
+        assert_eq!(hex(&digest(b"abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq")),
+                   "248d6a61d20638b8e5c026930c3e6039a33ce45964ff2167f6ecedd419db06c1");
+        assert_eq!(hex(&digest(&vec![b'a'; 1_000_000])),
+                   "cdc76e5c9914fb9281a1c7e284d73e67f1809a48a497200e046d39ccc7112cd0");
+    }
+
+    #[test]
+    fn split_updates() {
+        // Lengths around the padding and block boundaries, fed in uneven pieces.
+        let data: Vec<u8> = (0..300u32).map(|i| (i * 7 + 3) as u8).collect();
+        for length in 0..data.len() {
+            let expected = digest(&data[..length]);
+            for &step in [1, 3, 63, 64, 65].iter() {
+                let mut hasher = Sha256::new();
+                for chunk in data[..length].chunks(step) {
+                    hasher.update(chunk);
+                }
+                assert_eq!(hasher.finish(), expected, "length {} step {}", length, step);
+            }
+        }
+    }
+}
@@ -1,6 +1,7 @@
 use io::{Write, Error as IoError};
 use board_misoc::{csr, cache};
 use sched::{Io, TcpListener, TcpStream, Error as SchedError};
+use service;
 use analyzer_proto::*;
 
 const BUFFER_SIZE: usize = 512 * 1024;
@@ -63,7 +64,10 @@ fn worker(stream: &mut TcpStream) -> Result<(), IoError<SchedError>> {
 }
 
 pub fn thread(io: Io) {
-    let listener = TcpListener::new(&io, 65535);
+    // Dumps are served one at a time, so only the buffer size is configurable.
+    let buffer_size = service::read_buffer_size("analyzer", 65535);
+
+    let listener = TcpListener::new(&io, buffer_size);
     listener.listen(1382).expect("analyzer: cannot listen");
 
     loop {
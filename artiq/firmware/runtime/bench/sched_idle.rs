@@ -0,0 +1,164 @@
+// Host model of the cost of an idle `Scheduler::run()` pass with N threads, comparing
+// polled waits (`Io::until`) with notified waits (`Io::until_notified`).
+//
+// Threads are represented only by their wait requests; every thread is parked on
+// a socket condition that is false, which is the steady state of an idle core
+// device. Generator switching is not modelled. Build and run on the host with:
+//
+//   rustc -O bench/sched_idle.rs -o /tmp/sched_idle && /tmp/sched_idle
+
+use std::cell::{Cell, RefCell};
+use std::rc::Rc;
+use std::time::Instant;
+use std::hint::black_box;
+
+const PASSES: u32 = 2_000_000;
+
+struct Socket {
+    can_recv: bool,
+    may_recv: bool
+}
+
+struct WaitRequest {
+    event:   Option<Box<dyn FnMut() -> bool>>,
+    timeout: Option<u64>,
+    notify:  Option<(Rc<Cell<u32>>, u32)>
+}
+
+struct Thread {
+    interrupted: bool,
+    waiting_for: WaitRequest
+}
+
+type Threads = RefCell<Vec<Rc<RefCell<Thread>>>>;
+
+fn now_ms() -> u64 {
+    black_box(12345)
+}
+
+// The thread walk of `Scheduler::run()` when every wait is polled.
+fn run_polled(threads: &Threads, run_idx: &mut usize) -> bool {
+    let thread_count = threads.borrow().len();
+    let now = now_ms();
+    let start_idx = *run_idx;
+    loop {
+        *run_idx = (*run_idx + 1) % thread_count;
+        let handle = threads.borrow()[*run_idx].clone();
+        let mut thread = handle.borrow_mut();
+        let Thread { ref mut interrupted, ref mut waiting_for } = *thread;
+        if *interrupted {
+            return true
+        } else if waiting_for.event.is_none() && waiting_for.timeout.is_none() {
+            return true
+        } else if waiting_for.timeout.map(|instant| now >= instant).unwrap_or(false) {
+            return true
+        } else if waiting_for.event.as_mut().map(|event| event()).unwrap_or(false) {
+            return true
+        } else if *run_idx == start_idx {
+            return false
+        }
+    }
+}
+
+struct Scheduler {
+    run_idx: usize,
+    idle:    bool
+}
+
+// The thread walk of `Scheduler::run()` with notified waits and the idle shortcut.
+fn run_notified(threads: &Threads, scheduler: &mut Scheduler) -> bool {
+    if scheduler.idle {
+        return false
+    }
+
+    let thread_count = threads.borrow().len();
+    let now = now_ms();
+    let start_idx = scheduler.run_idx;
+    let mut run_idx = start_idx;
+    let mut polled = false;
+    loop {
+        run_idx = (run_idx + 1) % thread_count;
+        let handle = threads.borrow()[run_idx].clone();
+        let mut thread = handle.borrow_mut();
+        let Thread { ref mut interrupted, ref mut waiting_for } = *thread;
+        let notified = match waiting_for.notify {
+            None => {
+                polled |= waiting_for.event.is_some();
+                true
+            }
+            Some((ref notify, ref mut generation)) => {
+                let notified = notify.get() != *generation;
+                *generation = notify.get();
+                notified
+            }
+        };
+        if *interrupted {
+            return true
+        } else if waiting_for.event.is_none() && waiting_for.timeout.is_none() {
+            return true
+        } else if waiting_for.timeout.map(|instant| now >= instant).unwrap_or(false) {
+            return true
+        } else if notified && waiting_for.event.as_mut().map(|event| event()).unwrap_or(false) {
+            return true
+        } else if run_idx == start_idx {
+            scheduler.idle = !polled;
+            return false
+        }
+    }
+}
+
+fn spawn_parked(count: usize, network: &Rc<Cell<u32>>, notified: bool) -> Threads {
+    let sockets = Rc::new(RefCell::new(Vec::new()));
+    let mut threads = Vec::new();
+    for index in 0..count {
+        sockets.borrow_mut().push(Socket { can_recv: false, may_recv: true });
+        let sockets = sockets.clone();
+        let event: Box<dyn FnMut() -> bool> = Box::new(move || {
+            let socket = &sockets.borrow()[index];
+            socket.can_recv || !socket.may_recv
+        });
+        threads.push(Rc::new(RefCell::new(Thread {
+            interrupted: false,
+            waiting_for: WaitRequest {
+                event:   Some(event),
+                timeout: None,
+                notify:  if notified { Some((network.clone(), network.get())) } else { None }
+            }
+        })));
+    }
+    RefCell::new(threads)
+}
+
+fn ns_per_pass<F: FnMut()>(mut f: F) -> f64 {
+    let start = Instant::now();
+    for _ in 0..PASSES {
+        f()
+    }
+    start.elapsed().as_nanos() as f64 / PASSES as f64
+}
+
+fn main() {
+    println!("{:>8} {:>16} {:>16} {:>24}",
+             "threads", "polled ns/pass", "notified ns/pass", "notified every pass");
+    for &count in [4, 8, 16, 32, 64].iter() {
+        let network = Rc::new(Cell::new(0));
+
+        let threads = spawn_parked(count, &network, false);
+        let mut run_idx = 0;
+        let polled = ns_per_pass(|| { black_box(run_polled(&threads, &mut run_idx)); });
+
+        let threads = spawn_parked(count, &network, true);
+        let mut scheduler = Scheduler { run_idx: 0, idle: false };
+        let notified = ns_per_pass(|| { black_box(run_notified(&threads, &mut scheduler)); });
+
+        // Worst case: the network interface reports activity before every pass.
+        let mut scheduler = Scheduler { run_idx: 0, idle: false };
+        let busy = ns_per_pass(|| {
+            network.set(network.get() + 1);
+            scheduler.idle = false;
+            black_box(run_notified(&threads, &mut scheduler));
+        });
+
+        println!("{:>8} {:>16.1} {:>16.1} {:>24.1}", count, polled, notified, busy);
+    }
+}
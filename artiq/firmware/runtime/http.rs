@@ -0,0 +1,261 @@
+use core::str;
+use core::cell::RefCell;
+use core::fmt::Write as FmtWrite;
+use alloc::String;
+
+use io::{Read, Write, Error as IoError};
+use board_misoc::{clock, config};
+#[cfg(has_ethmac)]
+use board_misoc::ethmac;
+use board_artiq::drtio_routing;
+use sched::{Io, TcpListener, TcpStream, Error as SchedError};
+use urc::Urc;
+use service::{self, ServiceConfig, ClientCount};
+use rtio_clocking;
+use rtio_mgt;
+use session;
+
+const MAX_REQUEST_SIZE: usize = 1024;
+
+fn kernel_state_name(state: session::KernelState) -> &'static str {
+    match state {
+        session::KernelState::Absent  => "absent",
+        session::KernelState::Loaded  => "loaded",
+        session::KernelState::Running => "running",
+        session::KernelState::RpcWait => "rpc_wait",
+    }
+}
+
+fn metrics(up_destinations: &[bool; drtio_routing::DEST_COUNT]) -> String {
+    let mut body = String::new();
+
+    macro_rules! metric {
+        ($name:expr, $ty:expr, $help:expr) => {
+            write!(&mut body, "# HELP {} {}\n# TYPE {} {}\n", $name, $help, $name, $ty).unwrap()
+        }
+    }
+
+    metric!("artiq_uptime_seconds", "counter", "Time since the runtime started.");
+    let uptime_us = clock::get_us();
+    write!(&mut body, "artiq_uptime_seconds {}.{:06}\n",
+           uptime_us / 1_000_000, uptime_us % 1_000_000).unwrap();
+
+    let heap = unsafe { ::ALLOC.stats() };
+    metric!("artiq_heap_bytes", "gauge", "Heap memory by use.");
+    write!(&mut body, "artiq_heap_bytes{{use=\"busy\"}} {}\n", heap.busy).unwrap();
+    write!(&mut body, "artiq_heap_bytes{{use=\"idle\"}} {}\n", heap.idle).unwrap();
+    write!(&mut body, "artiq_heap_bytes{{use=\"meta\"}} {}\n", heap.meta).unwrap();
+    metric!("artiq_heap_blocks", "gauge", "Heap blocks by use.");
+    write!(&mut body, "artiq_heap_blocks{{use=\"busy\"}} {}\n", heap.busy_blocks).unwrap();
+    write!(&mut body, "artiq_heap_blocks{{use=\"idle\"}} {}\n", heap.idle_blocks).unwrap();
+    metric!("artiq_heap_largest_idle_bytes", "gauge", "Size of the largest free heap block.");
+    write!(&mut body, "artiq_heap_largest_idle_bytes {}\n", heap.largest_idle).unwrap();
+
+    #[cfg(has_ethmac)]
+    {
+        let net_stats = ethmac::EthernetStatistics::new();
+        metric!("artiq_ethernet_frames_total", "counter", "Ethernet frames by direction.");
+        write!(&mut body, "artiq_ethernet_frames_total{{direction=\"rx\"}} {}\n",
+               net_stats.rx_frames).unwrap();
+        write!(&mut body, "artiq_ethernet_frames_total{{direction=\"tx\"}} {}\n",
+               net_stats.tx_frames).unwrap();
+        metric!("artiq_ethernet_tx_full_total", "counter",
+                "Transmission stalls because all TX slots were busy.");
+        write!(&mut body, "artiq_ethernet_tx_full_total {}\n", net_stats.tx_full).unwrap();
+        if let Some(link_up) = ethmac::phy_link_up() {
+            metric!("artiq_ethernet_phy_link_up", "gauge", "Whether the Ethernet PHY has a link.");
+            write!(&mut body, "artiq_ethernet_phy_link_up {}\n", link_up as u8).unwrap();
+        }
+        metric!("artiq_ethernet_rx_errors_total", "counter", "Ethernet receive errors by kind.");
+        write!(&mut body, "artiq_ethernet_rx_errors_total{{kind=\"preamble\"}} {}\n",
+               net_stats.rx_preamble_errors).unwrap();
+        write!(&mut body, "artiq_ethernet_rx_errors_total{{kind=\"crc\"}} {}\n",
+               net_stats.rx_crc_errors).unwrap();
+        write!(&mut body, "artiq_ethernet_rx_errors_total{{kind=\"dropped\"}} {}\n",
+               net_stats.rx_dropped).unwrap();
+    }
+
+    metric!("artiq_rtio_clock_locked", "gauge", "Whether the RTIO clock PLL is locked.");
+    write!(&mut body, "artiq_rtio_clock_locked {}\n", rtio_clocking::crg::check() as u8).unwrap();
+
+    if rtio_mgt::drtio::link_count() > 0 {
+        metric!("artiq_drtio_link_up", "gauge", "Whether a DRTIO link is up.");
+        for linkno in 0..rtio_mgt::drtio::link_count() {
+            write!(&mut body, "artiq_drtio_link_up{{link=\"{}\"}} {}\n",
+                   linkno, rtio_mgt::drtio::link_up(linkno as u8) as u8).unwrap();
+        }
+    }
+
+    metric!("artiq_rtio_destination_up", "gauge", "Whether an RTIO destination is up.");
+    for (destination, &up) in up_destinations.iter().enumerate() {
+        if up {
+            write!(&mut body, "artiq_rtio_destination_up{{destination=\"{}\"}} 1\n",
+                   destination).unwrap();
+        }
+    }
+
+    let status = session::status();
+    metric!("artiq_session_connected", "gauge", "Whether a host is connected to the session port.");
+    write!(&mut body, "artiq_session_connected {}\n", status.host_connected as u8).unwrap();
+    metric!("artiq_kernel_state", "gauge", "Current state of the kernel CPU.");
+    for &state in [session::KernelState::Absent, session::KernelState::Loaded,
+                   session::KernelState::Running, session::KernelState::RpcWait].iter() {
+        write!(&mut body, "artiq_kernel_state{{state=\"{}\"}} {}\n",
+               kernel_state_name(state), (status.kernel_state == state) as u8).unwrap();
+    }
+
+    body
+}
+
+fn status(up_destinations: &[bool; drtio_routing::DEST_COUNT]) -> String {
+    let mut body = String::new();
+
+    let uptime_us = clock::get_us();
+    let heap = unsafe { ::ALLOC.stats() };
+    write!(&mut body, "{{\"uptime_us\":{},\"heap\":{{\"busy\":{},\"idle\":{},\"meta\":{}}}",
+           uptime_us, heap.busy, heap.idle, heap.meta).unwrap();
+
+    #[cfg(has_ethmac)]
+    {
+        let net_stats = ethmac::EthernetStatistics::new();
+        write!(&mut body, ",\"ethernet\":{{\"rx_frames\":{},\"tx_frames\":{},\"tx_full\":{},\
+                           \"rx_preamble_errors\":{},\"rx_crc_errors\":{},\"rx_dropped\":{}",
+               net_stats.rx_frames, net_stats.tx_frames, net_stats.tx_full,
+               net_stats.rx_preamble_errors, net_stats.rx_crc_errors,
+               net_stats.rx_dropped).unwrap();
+        match ethmac::phy_link_up() {
+            Some(link_up) => write!(&mut body, ",\"phy_link_up\":{}}}", link_up).unwrap(),
+            None => write!(&mut body, ",\"phy_link_up\":null}}").unwrap()
+        }
+    }
+
+    write!(&mut body, ",\"rtio_clock_locked\":{}", rtio_clocking::crg::check()).unwrap();
+
+    write!(&mut body, ",\"drtio_links_up\":[").unwrap();
+    for linkno in 0..rtio_mgt::drtio::link_count() {
+        if linkno > 0 { body.push(',') }
+        write!(&mut body, "{}", rtio_mgt::drtio::link_up(linkno as u8)).unwrap();
+    }
+    write!(&mut body, "],\"rtio_destinations_up\":[").unwrap();
+    let mut first = true;
+    for (destination, &up) in up_destinations.iter().enumerate() {
+        if up {
+            if !first { body.push(',') }
+            write!(&mut body, "{}", destination).unwrap();
+            first = false;
+        }
+    }
+
+    let status = session::status();
+    write!(&mut body, "],\"session\":{{\"connected\":{},\"kernel_state\":\"{}\"}}}}\n",
+           status.host_connected, kernel_state_name(status.kernel_state)).unwrap();
+
+    body
+}
+
+fn read_request_path(stream: &mut TcpStream, idle_timeout: Option<u64>)
+                    -> Result<Option<String>, IoError<SchedError>> {
+    let mut request = [0; MAX_REQUEST_SIZE];
+    let mut length = 0;
+    // HTTP/1.0 has no pipelining, so the request ends at the first empty line.
+    while !request[..length].ends_with(b"\r\n\r\n") && !request[..length].ends_with(b"\n\n") {
+        if length == request.len() {
+            return Ok(None)
+        }
+        if !service::wait_request(stream, idle_timeout).map_err(IoError::Other)? {
+            return Err(IoError::UnexpectedEnd)
+        }
+        let read = stream.read(&mut request[length..])?;
+        if read == 0 {
+            return Err(IoError::UnexpectedEnd)
+        }
+        length += read;
+    }
+
+    let line = match str::from_utf8(&request[..length]).ok().and_then(|s| s.lines().next()) {
+        Some(line) => line,
+        None => return Ok(None)
+    };
+    let mut words = line.split(' ');
+    match (words.next(), words.next()) {
+        (Some("GET"), Some(path)) => Ok(Some(String::from(path))),
+        _ => Ok(None)
+    }
+}
+
+fn respond(stream: &mut TcpStream, status: &str, content_type: &str,
+           body: &str) -> Result<(), IoError<SchedError>> {
+    let mut header = String::new();
+    write!(&mut header, "HTTP/1.0 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\n\
+                         Connection: close\r\n\r\n",
+           status, content_type, body.len()).unwrap();
+    stream.write_all(header.as_bytes())?;
+    stream.write_all(body.as_bytes())?;
+    stream.flush()?;
+    Ok(())
+}
+
+fn worker(stream: &mut TcpStream, idle_timeout: Option<u64>,
+          up_destinations: &Urc<RefCell<[bool; drtio_routing::DEST_COUNT]>>)
+         -> Result<(), IoError<SchedError>> {
+    match read_request_path(stream, idle_timeout)?.as_ref().map(|path| path.as_str()) {
+        Some("/metrics") =>
+            respond(stream, "200 OK", "text/plain; version=0.0.4",
+                    &metrics(&up_destinations.borrow())),
+        Some("/status") =>
+            respond(stream, "200 OK", "application/json",
+                    &status(&up_destinations.borrow())),
+        Some(_) =>
+            respond(stream, "404 Not Found", "text/plain", "not found\n"),
+        None =>
+            respond(stream, "400 Bad Request", "text/plain", "bad request\n"),
+    }
+}
+
+pub fn thread(io: Io, up_destinations: &Urc<RefCell<[bool; drtio_routing::DEST_COUNT]>>) {
+    let port = match config::read_str("http_port", |r| r.map(|s| s.parse::<u16>())) {
+        Ok(Ok(port)) => port,
+        Ok(Err(_)) => {
+            error!("invalid `http_port` config key");
+            return
+        }
+        Err(_) => return
+    };
+
+    let config = ServiceConfig::read("http", ServiceConfig {
+        buffer_size:  4096,
+        max_clients:  2,
+        idle_timeout: Some(5000)
+    });
+    let clients = ClientCount::new(config.max_clients);
+
+    let listener = TcpListener::new(&io, config.buffer_size);
+    listener.listen(port).expect("http: cannot listen");
+    info!("status server listening on port {}", port);
+
+    loop {
+        let stream = listener.accept().expect("http: cannot accept");
+        let slot = match clients.acquire() {
+            Some(slot) => slot,
+            None => {
+                warn!("refusing connection from {}: too many clients",
+                      stream.remote_endpoint());
+                let _ = stream.abort();
+                continue
+            }
+        };
+        stream.set_timeout(config.idle_timeout);
+        let stream = stream.into_handle();
+        let up_destinations = up_destinations.clone();
+        io.spawn("http worker", 8192, move |io| {
+            let _slot = slot;
+            let mut stream = TcpStream::from_handle(&io, stream);
+            match worker(&mut stream, config.idle_timeout, &up_destinations) {
+                Ok(()) => (),
+                Err(IoError::UnexpectedEnd) => (),
+                Err(err) => warn!("http request from {} failed: {}", stream.remote_endpoint(), err)
+            }
+            let _ = stream.close();
+        });
+    }
+}
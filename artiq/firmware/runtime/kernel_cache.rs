@@ -0,0 +1,67 @@
+use alloc::Vec;
+use sha256;
+
+/// SHA-256 digest of a kernel library.
+pub type Hash = [u8; 32];
+
+/// Recently loaded kernel libraries, keyed by the hash of their contents.
+#[derive(Debug)]
+pub struct KernelCache {
+    // Least recently used first.
+    entries:  Vec<(Hash, Vec<u8>)>,
+    size:     usize,
+    capacity: usize
+}
+
+impl KernelCache {
+    pub fn new(capacity: usize) -> KernelCache {
+        KernelCache {
+            entries:  Vec::new(),
+            size:     0,
+            capacity: capacity
+        }
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.capacity > 0
+    }
+
+    /// Removes the library with the given hash from the cache and returns it.
+    /// It should be put back with `insert` once it has been loaded.
+    pub fn take(&mut self, hash: &Hash) -> Option<Vec<u8>> {
+        let index = self.entries.iter().position(|&(ref entry_hash, _)| entry_hash == hash)?;
+        let (_, library) = self.entries.remove(index);
+        self.size -= library.len();
+        Some(library)
+    }
+
+    /// Adds a library to the cache as the most recently used one, evicting the
+    /// least recently used libraries to stay within capacity.
+    pub fn insert(&mut self, hash: Hash, library: Vec<u8>) {
+        let _ = self.take(&hash);
+        if library.len() > self.capacity {
+            return
+        }
+
+        while self.size + library.len() > self.capacity {
+            let (_, evicted) = self.entries.remove(0);
+            self.size -= evicted.len();
+        }
+        self.size += library.len();
+        self.entries.push((hash, library));
+    }
+
+    /// Like `insert`, for a library uploaded together with its hash by the host.
+    /// The library is only cached if its contents match `hash`, since it would
+    /// otherwise be loaded whenever the host asks for a different kernel.
+    pub fn insert_uploaded(&mut self, hash: Hash, library: Vec<u8>) -> Result<(), ()> {
+        if !self.enabled() || library.len() > self.capacity {
+            return Ok(())
+        }
+        if sha256::digest(&library) != hash {
+            return Err(())
+        }
+        self.insert(hash, library);
+        Ok(())
+    }
+}
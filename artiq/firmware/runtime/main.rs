@@ -18,8 +18,12 @@ extern crate managed;
 extern crate smoltcp;
 
 extern crate alloc_list;
+#[cfg(feature = "alloc_tlsf")]
+extern crate alloc_tlsf;
 extern crate unwind_backtrace;
 extern crate io;
+extern crate inflate;
+extern crate sha256;
 #[macro_use]
 extern crate board_misoc;
 extern crate board_artiq;
@@ -46,11 +50,17 @@ mod rtio_mgt;
 
 mod urc;
 mod sched;
+mod service;
 mod cache;
+mod kernel_cache;
 mod rtio_dma;
 
 mod mgmt;
+mod syslog;
+mod sntp;
+mod http;
 mod profiler;
+mod perf;
 mod kernel;
 mod kern_hwreq;
 mod watchdog;
@@ -240,33 +250,57 @@ fn startup() {
     let aux_mutex = sched::Mutex::new();
 
     let mut scheduler = sched::Scheduler::new();
+    if let Ok(true) = config::read_str("stack_debug", |r| r.map(|s| s == "1")) {
+        info!("measuring thread stack usage");
+        scheduler.set_stack_debug(true);
+    }
+    let stall_warn_ms = match config::read_str("stall_warn_threshold", |r| r.map(|s| s.parse())) {
+        Ok(Ok(0)) => None,
+        Ok(Ok(threshold_ms)) => Some(threshold_ms),
+        _ => Some(1000)
+    };
+    let stall_panic_ms = match config::read_str("stall_panic_threshold", |r| r.map(|s| s.parse())) {
+        Ok(Ok(0)) => None,
+        Ok(Ok(threshold_ms)) => Some(threshold_ms),
+        _ => None
+    };
+    scheduler.set_stall_thresholds(stall_warn_ms, stall_panic_ms);
     let io = scheduler.io();
 
     rtio_mgt::startup(&io, &aux_mutex, &drtio_routing_table, &up_destinations);
+    syslog::startup(&io, protocol_addr);
+    sntp::startup(&io);
 
-    io.spawn(4096, mgmt::thread);
+    io.spawn("mgmt", 4096, mgmt::thread);
     {
         let aux_mutex = aux_mutex.clone();
         let drtio_routing_table = drtio_routing_table.clone();
         let up_destinations = up_destinations.clone();
-        io.spawn(16384, move |io| { session::thread(io, &aux_mutex, &drtio_routing_table, &up_destinations) });
+        let session = io.spawn("session", 16384, move |io| { session::thread(io, &aux_mutex, &drtio_routing_table, &up_destinations) });
+        session.set_priority(sched::Priority::High);
     }
     #[cfg(any(has_rtio_moninj, has_drtio))]
     {
         let aux_mutex = aux_mutex.clone();
         let drtio_routing_table = drtio_routing_table.clone();
-        io.spawn(4096, move |io| { moninj::thread(io, &aux_mutex, &drtio_routing_table) });
+        io.spawn("moninj", 4096, move |io| { moninj::thread(io, &aux_mutex, &drtio_routing_table) });
     }
     #[cfg(has_rtio_analyzer)]
-    io.spawn(4096, analyzer::thread);
+    io.spawn("analyzer", 4096, analyzer::thread);
 
     #[cfg(has_grabber)]
-    io.spawn(4096, grabber_thread);
+    io.spawn("grabber", 4096, grabber_thread);
+
+    {
+        let up_destinations = up_destinations.clone();
+        io.spawn("http", 4096, move |io| { http::thread(io, &up_destinations) });
+    }
 
     let mut net_stats = ethmac::EthernetStatistics::new();
     loop {
         scheduler.run();
 
+        let mut network_activity = false;
         {
             let sockets = &mut *scheduler.sockets().borrow_mut();
             loop {
@@ -277,17 +311,31 @@ fn startup() {
                     Err(smoltcp::Error::Unrecognized) => (),
                     Err(err) => debug!("network error: {}", err)
                 }
+                // A packet that was processed with an error may still have changed
+                // socket state, e.g. by resetting a connection.
+                network_activity = true;
             }
         }
+        if network_activity {
+            scheduler.notify_network();
+        }
 
         if let Some(_net_stats_diff) = net_stats.update() {
             debug!("ethernet mac:{}", ethmac::EthernetStatistics::new());
         }
+        ethmac::poll_phy_link();
     }
 }
 
+#[cfg(not(feature = "alloc_tlsf"))]
 #[global_allocator]
 static mut ALLOC: alloc_list::ListAlloc = alloc_list::EMPTY;
+#[cfg(feature = "alloc_tlsf")]
+#[global_allocator]
+static mut ALLOC: alloc_tlsf::TlsfAlloc = alloc_tlsf::EMPTY;
+#[cfg(all(feature = "alloc_debug", feature = "alloc_tlsf"))]
+compile_error!("the alloc_debug checks are only implemented by the default allocator, \
+                not by alloc_tlsf");
 static mut LOG_BUFFER: [u8; 1<<17] = [0; 1<<17];
 
 #[no_mangle]
@@ -315,6 +363,9 @@ pub extern fn exception(vect: u32, _regs: *const u32, pc: u32, ea: u32) {
                     #[cfg(has_timer1)]
                     () if irq::is_pending(csr::TIMER1_INTERRUPT) =>
                         profiler::sample(pc as usize),
+                    #[cfg(has_timer2)]
+                    () if irq::is_pending(csr::TIMER2_INTERRUPT) =>
+                        sched::check_stall(),
                     _ => panic!("spurious irq {}", irq::pending_mask().trailing_zeros())
                 }
             },
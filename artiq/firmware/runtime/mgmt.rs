@@ -1,11 +1,16 @@
 use log::{self, LevelFilter};
 
 use io::{Write, ProtoWrite, Error as IoError};
-use board_misoc::{config, boot};
+use board_misoc::{csr, config, boot, clock};
+#[cfg(has_ethmac)]
+use board_misoc::ethmac;
 use logger_artiq::BufferLogger;
 use mgmt_proto::*;
-use sched::{Io, TcpListener, TcpStream, Error as SchedError};
+use sched::{Io, TcpListener, TcpStream, ThreadState, Notify, Error as SchedError};
+use service::{self, ServiceConfig, ClientCount};
 use profiler;
+use perf;
+use rtio_mgt;
 
 impl From<SchedError> for Error<SchedError> {
     fn from(value: SchedError) -> Error<SchedError> {
@@ -13,21 +18,95 @@ impl From<SchedError> for Error<SchedError> {
     }
 }
 
-fn worker(io: &Io, stream: &mut TcpStream) -> Result<(), Error<SchedError>> {
+// Notified by the logger whenever a record has been buffered or the buffer released.
+static mut LOG_NOTIFY: Option<Notify> = None;
+
+fn notify_log() {
+    if let Some(notify) = unsafe { LOG_NOTIFY.as_ref() } {
+        notify.notify()
+    }
+}
+
+fn write_profile(stream: &mut TcpStream, profile: &mut profiler::Profile,
+                 duration_us: u64) -> Result<(), IoError<SchedError>> {
+    Reply::Profile {
+        interval_us: profile.interval_us as u32,
+        samples:     profile.samples,
+        dropped:     profile.dropped,
+        duration_us: duration_us,
+        ident:       csr::CONFIG_IDENTIFIER_STR
+    }.write_to(stream)?;
+    stream.write_u32(profile.threads().len() as u32)?;
+    for name in profile.threads() {
+        stream.write_string(name)?;
+    }
+    {
+        let hits = profile.hits();
+        stream.write_u32(hits.len() as u32)?;
+        for (&(thread, addr), &count) in hits.iter() {
+            stream.write_u8(thread)?;
+            stream.write_u32(addr.as_raw() as u32)?;
+            stream.write_u32(count)?;
+        }
+    }
+    {
+        let edges = profile.edges();
+        stream.write_u32(edges.len() as u32)?;
+        for (&(thread, caller, callee), &count) in edges.iter() {
+            stream.write_u8(thread)?;
+            stream.write_u32(caller.as_raw() as u32)?;
+            stream.write_u32(callee.as_raw() as u32)?;
+            stream.write_u32(count)?;
+        }
+    }
+    Ok(())
+}
+
+// Sends the samples of every window as it closes, until the client disconnects
+// or the profiler is stopped. The profile of the closed window is sent while
+// the next one is being recorded into `spare`.
+fn stream_profile(io: &Io, stream: &mut TcpStream, mut spare: profiler::Profile,
+                  window_ms: u64) -> Result<(), Error<SchedError>> {
+    loop {
+        io.sleep(window_ms)?;
+        if !stream.may_recv() {
+            return Ok(())
+        }
+
+        spare.reset();
+        let mut window = match profiler::replace(spare) {
+            Ok(window) => window,
+            Err(_) => return Ok(())
+        };
+        let duration_us = window.duration_us();
+        write_profile(stream, &mut window, duration_us)?;
+        spare = window;
+    }
+}
+
+fn worker(io: &Io, stream: &mut TcpStream,
+          idle_timeout: Option<u64>) -> Result<(), Error<SchedError>> {
+    if !service::wait_request(stream, idle_timeout)? {
+        return Ok(())
+    }
     read_magic(stream)?;
     info!("new connection from {}", stream.remote_endpoint());
 
+    let log_notify = unsafe { LOG_NOTIFY.clone().unwrap() };
     loop {
+        if !service::wait_request(stream, idle_timeout)? {
+            return Ok(())
+        }
         match Request::read_from(stream)? {
             Request::GetLog => {
                 BufferLogger::with(|logger| {
-                    let mut buffer = io.until_ok(|| logger.buffer())?;
+                    let mut buffer = io.until_ok(&log_notify, || logger.buffer())?;
                     Reply::LogContent(buffer.extract()).write_to(stream)
                 })?;
             }
             Request::ClearLog => {
                 BufferLogger::with(|logger| -> Result<(), Error<SchedError>> {
-                    let mut buffer = io.until_ok(|| logger.buffer())?;
+                    let mut buffer = io.until_ok(&log_notify, || logger.buffer())?;
                     Ok(buffer.clear())
                 })?;
 
@@ -36,11 +115,13 @@ fn worker(io: &Io, stream: &mut TcpStream) -> Result<(), Error<SchedError>> {
             Request::PullLog => {
                 BufferLogger::with(|logger| -> Result<(), Error<SchedError>> {
                     loop {
+                        io.until_notified(&log_notify, || logger.has_records())?;
+
                         // Do this *before* acquiring the buffer, since that sets the log level
                         // to OFF.
                         let log_level = log::max_level();
 
-                        let mut buffer = io.until_ok(|| logger.buffer())?;
+                        let mut buffer = io.until_ok(&log_notify, || logger.buffer())?;
                         if buffer.is_empty() { continue }
 
                         stream.write_string(buffer.extract())?;
@@ -115,32 +196,34 @@ fn worker(io: &Io, stream: &mut TcpStream) -> Result<(), Error<SchedError>> {
             }
             Request::GetProfile => {
                 profiler::pause(|profile| {
-                    let profile = match profile {
-                        None => return Reply::Unavailable.write_to(stream),
-                        Some(profile) => profile
-                    };
-
-                    Reply::Profile.write_to(stream)?;
-                    {
-                        let hits = profile.hits();
-                        stream.write_u32(hits.len() as u32)?;
-                        for (&addr, &count) in hits.iter() {
-                            stream.write_u32(addr.as_raw() as u32)?;
-                            stream.write_u32(count)?;
+                    match profile {
+                        None => Reply::Unavailable.write_to(stream),
+                        Some(profile) => {
+                            let duration_us = profile.duration_us();
+                            write_profile(stream, profile, duration_us)
                         }
                     }
-                    {
-                        let edges = profile.edges();
-                        stream.write_u32(edges.len() as u32)?;
-                        for (&(caller, callee), &count) in edges.iter() {
-                            stream.write_u32(caller.as_raw() as u32)?;
-                            stream.write_u32(callee.as_raw() as u32)?;
-                            stream.write_u32(count)?;
-                        }
+                })?;
+            }
+            Request::StreamProfile { interval_us, window_ms, hits_size, edges_size } => {
+                if window_ms == 0 {
+                    Reply::Error.write_to(stream)?;
+                    continue
+                }
+                match profiler::start(interval_us as u64,
+                                      hits_size as usize, edges_size as usize) {
+                    Ok(()) => Reply::Success.write_to(stream)?,
+                    Err(()) => {
+                        Reply::Unavailable.write_to(stream)?;
+                        continue
                     }
+                }
 
-                    Ok(())
-                })?;
+                let spare = profiler::Profile::new(interval_us as u64,
+                                                   hits_size as usize, edges_size as usize);
+                let result = stream_profile(io, stream, spare, window_ms as u64);
+                profiler::stop();
+                return result
             }
 
             Request::Hotswap(firmware) => {
@@ -162,22 +245,126 @@ fn worker(io: &Io, stream: &mut TcpStream) -> Result<(), Error<SchedError>> {
                 unsafe { boot::reset() }
             }
 
-            Request::DebugAllocator =>
-                unsafe { println!("{}", ::ALLOC) },
+            Request::DebugAllocator => {
+                let stats = unsafe { ::ALLOC.stats() };
+                Reply::HeapStatistics {
+                    busy:          stats.busy as u32,
+                    idle:          stats.idle as u32,
+                    meta:          stats.meta as u32,
+                    busy_blocks:   stats.busy_blocks as u32,
+                    idle_blocks:   stats.idle_blocks as u32,
+                    largest_idle:  stats.largest_idle as u32,
+                    fragmentation: stats.fragmentation() as u32
+                }.write_to(stream)?;
+            }
+            Request::CheckHeap => {
+                match unsafe { ::ALLOC.check() } {
+                    Ok(()) => Reply::Success.write_to(stream)?,
+                    Err(err) => {
+                        error!("heap corruption detected: {}", err);
+                        Reply::HeapCorruption(&format!("{}", err)).write_to(stream)?
+                    }
+                }
+            }
+
+            Request::GetClockMapping => {
+                let clock_us = clock::get_us();
+                let rtio_counter = rtio_mgt::get_counter();
+                Reply::ClockMapping {
+                    utc_us:       clock::utc_offset_us().map(|offset| offset + clock_us),
+                    clock_us:     clock_us,
+                    rtio_counter: rtio_counter
+                }.write_to(stream)?;
+            }
+
+            #[cfg(has_ethmac)]
+            Request::GetEthernetStatistics => {
+                let stats = ethmac::EthernetStatistics::new();
+                Reply::EthernetStatistics {
+                    rx_frames:          stats.rx_frames,
+                    tx_frames:          stats.tx_frames,
+                    tx_full:            stats.tx_full,
+                    rx_preamble_errors: stats.rx_preamble_errors,
+                    rx_crc_errors:      stats.rx_crc_errors,
+                    rx_dropped:         stats.rx_dropped,
+                    phy_link_up:        ethmac::phy_link_up()
+                }.write_to(stream)?;
+            }
+            #[cfg(not(has_ethmac))]
+            Request::GetEthernetStatistics =>
+                Reply::Unavailable.write_to(stream)?,
+
+            Request::GetThreads => {
+                let threads = io.threads();
+                Reply::ThreadList.write_to(stream)?;
+                stream.write_u32(threads.len() as u32)?;
+                for thread in threads.iter() {
+                    stream.write_string(thread.name)?;
+                    stream.write_u8(match thread.state {
+                        ThreadState::Running           => 0,
+                        ThreadState::Runnable          => 1,
+                        ThreadState::WaitingForEvent   => 2,
+                        ThreadState::WaitingForTimeout => 3,
+                        ThreadState::Terminated        => 4,
+                    })?;
+                    stream.write_u64(thread.spawned_at)?;
+                    stream.write_u8(thread.priority as u8)?;
+                    stream.write_u32(thread.stack_size as u32)?;
+                    stream.write_u32(thread.stack_used as u32)?;
+                }
+            }
+
+            Request::ConfigurePerfCounters { kernel_only, ref counters } => {
+                match perf::configure(kernel_only, counters) {
+                    Ok(()) => Reply::Success.write_to(stream)?,
+                    Err(()) => Reply::Error.write_to(stream)?
+                }
+            }
+            Request::ReadPerfCounters => {
+                let (kernel_only, counters) = perf::read();
+                Reply::PerfCounters { kernel_only: kernel_only }.write_to(stream)?;
+                stream.write_u32(counters.len() as u32)?;
+                for &(events, value) in counters.iter() {
+                    stream.write_u32(events.bits())?;
+                    stream.write_u32(value)?;
+                }
+            }
         };
     }
 }
 
 pub fn thread(io: Io) {
-    let listener = TcpListener::new(&io, 8192);
+    unsafe { LOG_NOTIFY = Some(Notify::new()) }
+    BufferLogger::with(|logger| logger.set_buffer_notify(notify_log));
+
+    let config = ServiceConfig::read("mgmt", ServiceConfig {
+        buffer_size:  8192,
+        max_clients:  8,
+        idle_timeout: None
+    });
+    let clients = ClientCount::new(config.max_clients);
+
+    let listener = TcpListener::new(&io, config.buffer_size);
     listener.listen(1380).expect("mgmt: cannot listen");
     info!("management interface active");
 
     loop {
-        let stream = listener.accept().expect("mgmt: cannot accept").into_handle();
-        io.spawn(4096, move |io| {
+        let stream = listener.accept().expect("mgmt: cannot accept");
+        let slot = match clients.acquire() {
+            Some(slot) => slot,
+            None => {
+                warn!("refusing connection from {}: too many clients",
+                      stream.remote_endpoint());
+                let _ = stream.abort();
+                continue
+            }
+        };
+        stream.set_timeout(config.idle_timeout);
+        let stream = stream.into_handle();
+        io.spawn("mgmt worker", 4096, move |io| {
+            let _slot = slot;
             let mut stream = TcpStream::from_handle(&io, stream);
-            match worker(&io, &mut stream) {
+            match worker(&io, &mut stream, config.idle_timeout) {
                 Ok(()) => (),
                 Err(Error::Io(IoError::UnexpectedEnd)) => (),
                 Err(err) => error!("aborted: {}", err)
@@ -5,6 +5,7 @@ use io::Error as IoError;
 use moninj_proto::*;
 use sched::{Io, Mutex, TcpListener, TcpStream, Error as SchedError};
 use urc::Urc;
+use service::{self, ServiceConfig, ClientCount};
 use board_misoc::clock;
 use board_artiq::drtio_routing;
 
@@ -116,16 +117,23 @@ macro_rules! dispatch {
 }
 
 fn connection_worker(io: &Io, _aux_mutex: &Mutex, _routing_table: &drtio_routing::RoutingTable,
-        mut stream: &mut TcpStream) -> Result<(), Error<SchedError>> {
+        mut stream: &mut TcpStream, idle_timeout: Option<u64>) -> Result<(), Error<SchedError>> {
     let mut probe_watch_list = BTreeMap::new();
     let mut inject_watch_list = BTreeMap::new();
     let mut next_check = 0;
 
+    if !service::wait_request(stream, idle_timeout)
+            .map_err(|err| Error::Io(IoError::Other(err)))? {
+        return Ok(())
+    }
     read_magic(&mut stream)?;
     info!("new connection from {}", stream.remote_endpoint());
 
+    // The monitoring loop never blocks on the socket, so track idleness here.
+    let mut last_recv = clock::get_ms();
     loop {
         if stream.can_recv() {
+            last_recv = clock::get_ms();
             let request = HostMessage::read_from(stream)?;
             trace!("moninj<-host {:?}", request);
 
@@ -159,6 +167,11 @@ fn connection_worker(io: &Io, _aux_mutex: &Mutex, _routing_table: &drtio_routing
             }
         } else if !stream.may_recv() {
             return Ok(())
+        } else if idle_timeout.map(|timeout| clock::get_ms() - last_recv > timeout)
+                              .unwrap_or(false) {
+            info!("closing idle connection from {}", stream.remote_endpoint());
+            stream.abort().map_err(|err| Error::Io(IoError::Other(err)))?;
+            return Ok(())
         }
 
         if clock::get_ms() > next_check {
@@ -200,17 +213,37 @@ fn connection_worker(io: &Io, _aux_mutex: &Mutex, _routing_table: &drtio_routing
 }
 
 pub fn thread(io: Io, aux_mutex: &Mutex, routing_table: &Urc<RefCell<drtio_routing::RoutingTable>>) {
-    let listener = TcpListener::new(&io, 2047);
+    let config = ServiceConfig::read("moninj", ServiceConfig {
+        buffer_size:  2047,
+        max_clients:  8,
+        idle_timeout: None
+    });
+    let clients = ClientCount::new(config.max_clients);
+
+    let listener = TcpListener::new(&io, config.buffer_size);
     listener.listen(1383).expect("moninj: cannot listen");
 
     loop {
         let aux_mutex = aux_mutex.clone();
         let routing_table = routing_table.clone();
-        let stream = listener.accept().expect("moninj: cannot accept").into_handle();
-        io.spawn(16384, move |io| {
+        let stream = listener.accept().expect("moninj: cannot accept");
+        let slot = match clients.acquire() {
+            Some(slot) => slot,
+            None => {
+                warn!("refusing connection from {}: too many clients",
+                      stream.remote_endpoint());
+                let _ = stream.abort();
+                continue
+            }
+        };
+        stream.set_timeout(config.idle_timeout);
+        let stream = stream.into_handle();
+        io.spawn("moninj worker", 16384, move |io| {
+            let _slot = slot;
             let routing_table = routing_table.borrow();
             let mut stream = TcpStream::from_handle(&io, stream);
-            match connection_worker(&io, &aux_mutex, &routing_table, &mut stream) {
+            match connection_worker(&io, &aux_mutex, &routing_table, &mut stream,
+                                    config.idle_timeout) {
                 Ok(()) => {},
                 Err(err) => error!("moninj aborted: {}", err)
             }
@@ -0,0 +1,89 @@
+use alloc::Vec;
+use board_artiq::pcr::{self, Counters};
+
+const MAX_COUNTERS: usize = pcr::MAX_COUNTERS as usize;
+
+struct State {
+    // Bits of the `Counters` assigned to each performance counter.
+    counters:    [u32; MAX_COUNTERS],
+    count:       usize,
+    kernel_only: bool,
+    // Values at the end of the last kernel run, if counting only during kernel runs.
+    latched:     [u32; MAX_COUNTERS],
+}
+
+static mut STATE: State = State {
+    counters:    [0; MAX_COUNTERS],
+    count:       0,
+    kernel_only: false,
+    latched:     [0; MAX_COUNTERS],
+};
+
+fn state() -> &'static mut State {
+    unsafe { &mut STATE }
+}
+
+fn reset(state: &State) {
+    for index in 0..state.count {
+        pcr::setup(index as u32, Counters::from_bits_truncate(state.counters[index]))
+    }
+}
+
+fn halt(state: &State) {
+    for index in 0..state.count {
+        pcr::setup(index as u32, Counters::empty())
+    }
+}
+
+/// Assigns the events counted by each of the first `sets.len()` performance counters
+/// of the comms CPU, and resets them. If `kernel_only` is set, the counters only run
+/// while a kernel is running, and are reset when the next kernel starts.
+pub fn configure(kernel_only: bool, sets: &[u32]) -> Result<(), ()> {
+    if sets.len() > pcr::count() as usize {
+        return Err(())
+    }
+    let mut counters = [0; MAX_COUNTERS];
+    for (index, &bits) in sets.iter().enumerate() {
+        counters[index] = Counters::from_bits(bits).ok_or(())?.bits();
+    }
+
+    let state = state();
+    halt(state);
+    state.counters    = counters;
+    state.count       = sets.len();
+    state.kernel_only = kernel_only;
+    state.latched     = [0; MAX_COUNTERS];
+    if !kernel_only {
+        reset(state)
+    }
+    Ok(())
+}
+
+/// Returns whether the counters only run while a kernel is running, and the events
+/// and value of each configured counter.
+pub fn read() -> (bool, Vec<(Counters, u32)>) {
+    let state = state();
+    let values = (0..state.count).map(|index| {
+        let value = if state.kernel_only { state.latched[index] }
+                    else { pcr::read(index as u32) };
+        (Counters::from_bits_truncate(state.counters[index]), value)
+    }).collect();
+    (state.kernel_only, values)
+}
+
+pub fn kernel_started() {
+    let state = state();
+    if state.kernel_only {
+        reset(state)
+    }
+}
+
+pub fn kernel_finished() {
+    let state = state();
+    if state.kernel_only {
+        for index in 0..state.count {
+            state.latched[index] = pcr::read(index as u32)
+        }
+        halt(state)
+    }
+}
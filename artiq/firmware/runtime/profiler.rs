@@ -5,6 +5,7 @@ use core::fmt;
 use core::num::NonZeroUsize;
 use alloc::Vec;
 use managed::ManagedMap;
+use board_misoc::clock;
 
 #[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
 pub struct Address(NonZeroUsize);
@@ -19,67 +20,144 @@ impl Address {
     }
 }
 
+/// Index of a thread in the thread table of a profile. Samples taken outside of
+/// any thread, e.g. while the scheduler polls the network, belong to thread 0.
+pub type ThreadIndex = u8;
+
+const MAX_THREADS: usize = 32;
+const NO_THREAD: &'static str = "(scheduler)";
+
+// Name of the thread currently resumed by the scheduler.
+static mut CURRENT_THREAD: Option<&'static str> = None;
+
+/// Records the thread that samples taken from now on are attributed to.
+pub fn set_thread(name: Option<&'static str>) {
+    unsafe { CURRENT_THREAD = name }
+}
+
 pub struct Profile {
-    hits:  Vec<Option<(Address, u32)>>,
-    edges: Vec<Option<((Address, Address), u32)>>,
+    pub interval_us: u64,
+    /// Value of `clock::get_us()` when the profiler was started.
+    pub started_at:  u64,
+    /// Number of samples taken, including the dropped ones.
+    pub samples:     u64,
+    /// Number of samples that could not be fully recorded because the hits or
+    /// edges storage was full.
+    pub dropped:     u64,
+    // Threads are identified by name, so that samples from all the threads
+    // running the same service are merged.
+    threads: [&'static str; MAX_THREADS],
+    thread_count: usize,
+    hits:  Vec<Option<((ThreadIndex, Address), u32)>>,
+    edges: Vec<Option<((ThreadIndex, Address, Address), u32)>>,
 }
 
 impl fmt::Debug for Profile {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "Profile {{ hits: vec![...; {}], edges: vec![...; {}] }}",
-               self.hits.len(), self.edges.len())
+        write!(f, "Profile {{ interval_us: {}, started_at: {}, samples: {}, dropped: {}, \
+                   threads: {:?}, hits: vec![...; {}], edges: vec![...; {}] }}",
+               self.interval_us, self.started_at, self.samples, self.dropped,
+               self.threads(), self.hits.len(), self.edges.len())
     }
 }
 
 impl Profile {
-    pub fn new(hits_size: usize, edges_size: usize) -> Profile {
+    pub fn new(interval_us: u64, hits_size: usize, edges_size: usize) -> Profile {
         let mut hits  = vec![None; hits_size];
         hits.shrink_to_fit();
         let mut edges = vec![None; edges_size];
         edges.shrink_to_fit();
         Profile {
+            interval_us: interval_us,
+            started_at:  clock::get_us(),
+            samples:     0,
+            dropped:     0,
+            threads:      [NO_THREAD; MAX_THREADS],
+            thread_count: 1,
             hits:  hits.into(),
             edges: edges.into(),
         }
     }
 
+    /// Discards all samples, keeping the storage, and restarts the capture.
+    pub fn reset(&mut self) {
+        self.started_at   = clock::get_us();
+        self.samples      = 0;
+        self.dropped      = 0;
+        self.thread_count = 1;
+        for hit in self.hits.iter_mut() {
+            *hit = None
+        }
+        for edge in self.edges.iter_mut() {
+            *edge = None
+        }
+    }
+
+    /// Returns the time elapsed since the profiler was started.
+    pub fn duration_us(&self) -> u64 {
+        clock::get_us() - self.started_at
+    }
+
     pub fn overhead(&self) -> usize {
-        let hit_size  = mem::size_of::<Option<(Address, u32)>>();
-        let edge_size = mem::size_of::<Option<(Address, u32)>>();
+        let hit_size  = mem::size_of::<Option<((ThreadIndex, Address), u32)>>();
+        let edge_size = mem::size_of::<Option<((ThreadIndex, Address, Address), u32)>>();
         self.hits.capacity() * hit_size +
             self.edges.capacity() * edge_size
     }
 
     pub fn has_edges(&self) -> bool {
-        self.edges.is_empty()
+        !self.edges.is_empty()
+    }
+
+    /// Returns the names of the threads that samples were attributed to, in the
+    /// order of their `ThreadIndex`.
+    pub fn threads(&self) -> &[&'static str] {
+        &self.threads[..self.thread_count]
     }
 
-    pub fn hits<'a>(&'a mut self) -> ManagedMap<'a, Address, u32> {
+    pub fn hits<'a>(&'a mut self) -> ManagedMap<'a, (ThreadIndex, Address), u32> {
         ManagedMap::Borrowed(&mut self.hits[..])
     }
 
-    pub fn edges<'a>(&'a mut self) -> ManagedMap<'a, (Address, Address), u32> {
+    pub fn edges<'a>(&'a mut self) -> ManagedMap<'a, (ThreadIndex, Address, Address), u32> {
         ManagedMap::Borrowed(&mut self.edges[..])
     }
 
-    pub fn record_hit(&mut self, addr: Address) -> Result<(), ()> {
+    pub fn thread_index(&mut self, name: Option<&'static str>) -> Result<ThreadIndex, ()> {
+        let name = match name {
+            None => return Ok(0),
+            Some(name) => name
+        };
+        if let Some(index) = self.threads().iter().position(|&thread| thread == name) {
+            return Ok(index as ThreadIndex)
+        }
+        if self.thread_count == MAX_THREADS {
+            return Err(())
+        }
+        self.threads[self.thread_count] = name;
+        self.thread_count += 1;
+        Ok((self.thread_count - 1) as ThreadIndex)
+    }
+
+    pub fn record_hit(&mut self, thread: ThreadIndex, addr: Address) -> Result<(), ()> {
         let mut hits = self.hits();
-        if let Some(count) = hits.get_mut(&addr) {
+        if let Some(count) = hits.get_mut(&(thread, addr)) {
             return Ok(*count = count.saturating_add(1))
         }
-        if let Err(_) = hits.insert(addr, 1) {
+        if let Err(_) = hits.insert((thread, addr), 1) {
             return Err(())
         }
         return Ok(())
     }
 
     #[allow(dead_code)]
-    pub fn record_edge(&mut self, caller: Address, callee: Address) -> Result<(), ()> {
+    pub fn record_edge(&mut self, thread: ThreadIndex,
+                       caller: Address, callee: Address) -> Result<(), ()> {
         let mut edges = self.edges();
-        if let Some(count) = edges.get_mut(&(caller, callee)) {
+        if let Some(count) = edges.get_mut(&(thread, caller, callee)) {
             return Ok(*count = count.saturating_add(1))
         }
-        if let Err(_) = edges.insert((caller, callee), 1) {
+        if let Err(_) = edges.insert((thread, caller, callee), 1) {
             return Err(())
         }
         Ok(())
@@ -90,7 +168,7 @@ impl Profile {
 mod imp {
     use unwind_backtrace::backtrace;
     use board_misoc::{csr, irq};
-    use super::{Address, Profile};
+    use super::{Address, Profile, CURRENT_THREAD};
 
     static mut PROFILE: Option<Profile> = None;
 
@@ -138,7 +216,7 @@ mod imp {
     pub fn start(interval_us: u64, hits_size: usize, edges_size: usize) -> Result<(), ()> {
         stop();
 
-        let profile = Profile::new(hits_size, edges_size);
+        let profile = Profile::new(interval_us, hits_size, edges_size);
         info!("starting at {}us interval using {} heap bytes",
               interval_us, profile.overhead());
 
@@ -198,6 +276,7 @@ mod imp {
 
     #[inline(always)] // make the top of backtrace predictable
     fn record(profile: &mut Profile, exn_pc: usize) -> Result<(), ()> {
+        let thread = profile.thread_index(unsafe { CURRENT_THREAD })?;
         let mut result = Ok(());
         let mut frame = -SKIP_FRAMES;
 
@@ -216,11 +295,11 @@ mod imp {
 
                 if frame == 0 {
                     result = result.and_then(|()|
-                        profile.record_hit(Address::new(pc)));
+                        profile.record_hit(thread, Address::new(pc)));
                     prev_pc = pc;
                 } else if frame > 0 {
                     result = result.and_then(|()|
-                        profile.record_edge(Address::new(pc),
+                        profile.record_edge(thread, Address::new(pc),
                                             Address::new(prev_pc)));
                 }
 
@@ -232,7 +311,7 @@ mod imp {
         // If we couldn't get anything useful out of a backtrace, at least
         // record a hit at the exception PC.
         if frame <= 0 {
-            result = profile.record_hit(Address::new(exn_pc));
+            result = profile.record_hit(thread, Address::new(exn_pc));
         }
 
         result
@@ -240,18 +319,23 @@ mod imp {
 
     #[inline(never)] // see above
     pub fn sample(pc: usize) {
-        let result = {
+        let first_dropped = {
             let mut profile = Lock::take().expect("cannot lock");
-            record(profile.as_mut().expect("profiler not running"), pc)
+            let profile = profile.as_mut().expect("profiler not running");
+            profile.samples += 1;
+            if record(profile, pc).is_err() {
+                profile.dropped += 1;
+                profile.dropped == 1
+            } else {
+                false
+            }
         };
 
-        if result.is_err() {
-            warn!("out of space");
-            stop();
-        } else {
-            unsafe {
-                csr::timer1::ev_pending_write(1);
-            }
+        if first_dropped {
+            warn!("out of space, dropping samples");
+        }
+        unsafe {
+            csr::timer1::ev_pending_write(1);
         }
     }
 }
@@ -276,3 +360,12 @@ mod imp {
 }
 
 pub use self::imp::*;
+
+/// Replaces the profile being recorded with `profile` and returns the previous one,
+/// or gives `profile` back if the profiler is not running.
+pub fn replace(profile: Profile) -> Result<Profile, Profile> {
+    pause(|current| match current {
+        Some(current) => Ok(mem::replace(current, profile)),
+        None => Err(profile)
+    })
+}
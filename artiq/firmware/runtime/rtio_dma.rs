@@ -1,12 +1,44 @@
-use core::mem;
+use core::{mem, ptr, slice};
 use alloc::{Vec, String, BTreeMap};
+use alloc::alloc::{alloc, dealloc, Layout};
 
 const ALIGNMENT: usize = 64;
 
+// A terminated DMA trace, aligned as required by the DMA engine.
+#[derive(Debug)]
+struct Trace {
+    ptr: *mut u8,
+    len: usize
+}
+
+impl Trace {
+    fn new(data: &[u8]) -> Trace {
+        let len = data.len() + 1;
+        unsafe {
+            let ptr = alloc(Layout::from_size_align_unchecked(len, ALIGNMENT));
+            if ptr.is_null() {
+                panic!("cannot allocate DMA trace of {} bytes", len)
+            }
+            ptr::copy_nonoverlapping(data.as_ptr(), ptr, data.len());
+            *ptr.offset(data.len() as isize) = 0;
+            Trace { ptr: ptr, len: len }
+        }
+    }
+
+    fn as_slice(&self) -> &[u8] {
+        unsafe { slice::from_raw_parts(self.ptr, self.len) }
+    }
+}
+
+impl Drop for Trace {
+    fn drop(&mut self) {
+        unsafe { dealloc(self.ptr, Layout::from_size_align_unchecked(self.len, ALIGNMENT)) }
+    }
+}
+
 #[derive(Debug)]
 struct Entry {
-    trace: Vec<u8>,
-    padding_len: usize,
+    trace: Trace,
     duration: u64
 }
 
@@ -41,26 +73,12 @@ impl Manager {
     pub fn record_stop(&mut self, duration: u64) {
         let mut trace = Vec::new();
         mem::swap(&mut self.recording_trace, &mut trace);
-        trace.push(0);
-        let data_len = trace.len();
-
-        // Realign.
-        trace.reserve(ALIGNMENT - 1);
-        let padding = ALIGNMENT - trace.as_ptr() as usize % ALIGNMENT;
-        let padding = if padding == ALIGNMENT { 0 } else { padding };
-        for _ in 0..padding {
-            // Vec guarantees that this will not reallocate
-            trace.push(0)
-        }
-        for i in 1..data_len + 1 {
-            trace[data_len + padding - i] = trace[data_len - i]
-        }
+        let trace = Trace::new(&trace);
 
         let mut name = String::new();
         mem::swap(&mut self.recording_name, &mut name);
         self.entries.insert(name, Entry {
             trace: trace,
-            padding_len: padding,
             duration: duration
         });
     }
@@ -72,7 +90,7 @@ impl Manager {
     pub fn with_trace<F, R>(&self, name: &str, f: F) -> R
             where F: FnOnce(Option<&[u8]>, u64) -> R {
         match self.entries.get(name) {
-            Some(entry) => f(Some(&entry.trace[entry.padding_len..]), entry.duration),
+            Some(entry) => f(Some(entry.trace.as_slice()), entry.duration),
             None => f(None, 0)
         }
     }
@@ -12,13 +12,23 @@ pub mod drtio {
     use super::*;
     use drtioaux;
 
+    static mut LINK_UP: [bool; csr::DRTIO.len()] = [false; csr::DRTIO.len()];
+
+    pub fn link_count() -> usize {
+        csr::DRTIO.len()
+    }
+
+    pub fn link_up(linkno: u8) -> bool {
+        unsafe { LINK_UP[linkno as usize] }
+    }
+
     pub fn startup(io: &Io, aux_mutex: &Mutex,
             routing_table: &Urc<RefCell<drtio_routing::RoutingTable>>,
             up_destinations: &Urc<RefCell<[bool; drtio_routing::DEST_COUNT]>>) {
         let aux_mutex = aux_mutex.clone();
         let routing_table = routing_table.clone();
         let up_destinations = up_destinations.clone();
-        io.spawn(4096, move |io| {
+        io.spawn("drtio links", 4096, move |io| {
             let routing_table = routing_table.borrow();
             link_thread(io, &aux_mutex, &routing_table, &up_destinations);
         });
@@ -274,6 +284,7 @@ pub mod drtio {
                 }
             }
             destination_survey(&io, aux_mutex, routing_table, &up_links, up_destinations);
+            unsafe { LINK_UP = up_links }
             io.sleep(200).unwrap();
         }
     }
@@ -314,13 +325,19 @@ pub mod drtio {
         _routing_table: &Urc<RefCell<drtio_routing::RoutingTable>>,
         _up_destinations: &Urc<RefCell<[bool; drtio_routing::DEST_COUNT]>>) {}
     pub fn reset(_io: &Io, _aux_mutex: &Mutex) {}
+    pub fn link_count() -> usize { 0 }
+    pub fn link_up(_linkno: u8) -> bool { false }
 }
 
 fn async_error_thread(io: Io) {
     loop {
         unsafe {
-            io.until(|| csr::rtio_core::async_error_read() != 0).unwrap();
+            // The error bits stay set until they are cleared below, so nothing is lost
+            // by checking them periodically instead of on every pass of the scheduler,
+            // which would keep it from ever becoming idle.
+            io.sleep(10).unwrap();
             let errors = csr::rtio_core::async_error_read();
+            if errors == 0 { continue }
             if errors & 1 != 0 {
                 error!("RTIO collision involving channel {}",
                        csr::rtio_core::collision_channel_read());
@@ -338,6 +355,15 @@ fn async_error_thread(io: Io) {
     }
 }
 
+// The kernel initiator CSRs are shared with the kernel CPU. Updating the counter
+// while a kernel reads it only gives that kernel a slightly later value.
+pub fn get_counter() -> u64 {
+    unsafe {
+        csr::rtio::counter_update_write(1);
+        csr::rtio::counter_read()
+    }
+}
+
 pub fn startup(io: &Io, aux_mutex: &Mutex,
         routing_table: &Urc<RefCell<drtio_routing::RoutingTable>>,
         up_destinations: &Urc<RefCell<[bool; drtio_routing::DEST_COUNT]>>) {
@@ -345,7 +371,7 @@ pub fn startup(io: &Io, aux_mutex: &Mutex,
     unsafe {
         csr::rtio_core::reset_phy_write(1);
     }
-    io.spawn(4096, async_error_thread);
+    io.spawn("rtio errors", 4096, async_error_thread);
 }
 
 pub fn reset(io: &Io, aux_mutex: &Mutex) {
@@ -2,9 +2,11 @@
 
 use core::mem;
 use core::result;
+use core::cmp::{self, Reverse};
 use core::cell::{Cell, RefCell};
 use alloc::Vec;
-use fringe::OwnedStack;
+use alloc::binary_heap::BinaryHeap;
+use fringe::{OwnedStack, Stack};
 use fringe::generator::{Generator, Yielder, State as GeneratorState};
 use smoltcp::time::Duration;
 use smoltcp::Error as NetworkError;
@@ -14,6 +16,7 @@ use smoltcp::socket::{SocketHandle, SocketRef};
 use io::{Read, Write};
 use board_misoc::clock;
 use urc::Urc;
+use profiler;
 
 #[derive(Fail, Debug)]
 pub enum Error {
@@ -33,10 +36,133 @@ impl From<NetworkError> for Error {
 
 type SocketSet = ::smoltcp::socket::SocketSet<'static, 'static, 'static>;
 
+/// A condition that threads can wait for without being polled by the scheduler.
+///
+/// A thread waiting with `Io::until_notified` only has its event checked again after
+/// `notify` has been called, so anything that may make the event true must notify.
+#[derive(Debug, Clone)]
+pub struct Notify(Urc<Cell<u32>>);
+
+// Set by every notification, so that an idle scheduler checks its threads again
+// even if the notifier was called from outside of a thread.
+static mut NOTIFIED: bool = false;
+
+impl Notify {
+    pub fn new() -> Notify {
+        Notify(Urc::new(Cell::new(0)))
+    }
+
+    pub fn notify(&self) {
+        self.0.set(self.0.get().wrapping_add(1));
+        unsafe { NOTIFIED = true }
+    }
+
+    fn generation(&self) -> u32 {
+        self.0.get()
+    }
+}
+
+// Shared between the scheduler, its threads and the timer interrupt to detect
+// threads that run for too long without yielding.
+#[derive(Debug)]
+struct StallWatchdog {
+    running:     Cell<Option<&'static str>>,
+    resumed_at:  Cell<u64>,
+    warn_after:  Cell<Option<u64>>,
+    panic_after: Cell<Option<u64>>
+}
+
+static mut STALL: StallWatchdog = StallWatchdog {
+    running:     Cell::new(None),
+    resumed_at:  Cell::new(0),
+    warn_after:  Cell::new(None),
+    panic_after: Cell::new(None)
+};
+
+fn stall() -> &'static StallWatchdog {
+    unsafe { &STALL }
+}
+
+impl StallWatchdog {
+    fn resume(&self, name: &'static str) {
+        self.resumed_at.set(clock::get_ms());
+        self.running.set(Some(name))
+    }
+
+    fn suspend(&self) {
+        self.running.set(None)
+    }
+
+    fn elapsed(&self) -> u64 {
+        clock::get_ms() - self.resumed_at.get()
+    }
+
+    fn check_panic(&self) {
+        if let Some(name) = self.running.get() {
+            let elapsed = self.elapsed();
+            if self.panic_after.get().map(|limit| elapsed > limit).unwrap_or(false) {
+                panic!("thread {:?} ran for {} ms without yielding", name, elapsed)
+            }
+        }
+    }
+
+    fn check_warn(&self) {
+        if let Some(name) = self.running.get() {
+            let elapsed = self.elapsed();
+            if self.warn_after.get().map(|limit| elapsed > limit).unwrap_or(false) {
+                warn!("thread {:?} ran for {} ms without yielding", name, elapsed)
+            }
+        }
+    }
+}
+
+#[cfg(has_timer2)]
+mod stall_timer {
+    use board_misoc::{csr, irq};
+
+    pub fn arm(interval_us: Option<u64>) {
+        unsafe {
+            irq::disable(csr::TIMER2_INTERRUPT);
+            csr::timer2::en_write(0);
+
+            if let Some(interval_us) = interval_us {
+                let reload = csr::CONFIG_CLOCK_FREQUENCY as u64 * interval_us / 1_000_000;
+                csr::timer2::load_write(reload);
+                csr::timer2::reload_write(reload);
+                csr::timer2::ev_pending_write(1);
+                csr::timer2::ev_enable_write(1);
+                irq::enable(csr::TIMER2_INTERRUPT);
+                csr::timer2::en_write(1);
+            }
+        }
+    }
+
+    pub fn acknowledge() {
+        unsafe { csr::timer2::ev_pending_write(1) }
+    }
+}
+
+#[cfg(not(has_timer2))]
+mod stall_timer {
+    pub fn arm(_interval_us: Option<u64>) {}
+
+    pub fn acknowledge() {}
+}
+
+/// Panics if a thread has been running for longer than the stall panic threshold.
+/// Called from the stall timer interrupt, so that a thread that never yields is
+/// caught while it is still running.
+pub fn check_stall() {
+    stall_timer::acknowledge();
+    stall().check_panic()
+}
+
 #[derive(Debug)]
 struct WaitRequest {
     event:   Option<*mut FnMut() -> bool>,
-    timeout: Option<u64>
+    timeout: Option<u64>,
+    // The notifier that gates checking the event, and its generation when last checked.
+    notify:  Option<(Notify, u32)>
 }
 
 unsafe impl Send for WaitRequest {}
@@ -48,6 +174,75 @@ enum WaitResult {
     Interrupted
 }
 
+// Unused stack space is filled with this pattern so that the high-water mark
+// can be found later.
+const STACK_FILL: u32 = 0x5354_4b21;
+
+// The lowest words of every stack hold this pattern; if it is overwritten,
+// the thread has overflowed its stack.
+const STACK_CANARY: u32 = 0xdead_c0de;
+const STACK_CANARY_WORDS: isize = 4;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ThreadState {
+    Running,
+    Runnable,
+    WaitingForEvent,
+    WaitingForTimeout,
+    Terminated
+}
+
+/// Runnable threads with high priority are resumed before those with normal
+/// priority, but at most every other time.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Priority {
+    Normal = 0,
+    High   = 1
+}
+
+#[derive(Debug, Clone)]
+pub struct ThreadInfo {
+    pub name:       &'static str,
+    pub spawned_at: u64,
+    pub priority:   Priority,
+    pub state:      ThreadState,
+    pub stack_size: usize,
+    pub stack_used: usize
+}
+
+// The part of a thread that can be inspected while the thread is running.
+#[derive(Debug)]
+struct Descriptor {
+    name:        &'static str,
+    spawned_at:  u64,
+    priority:    Cell<Priority>,
+    stack_limit: *const u32,
+    stack_base:  *const u32,
+    stack_peak:  Cell<usize>
+}
+
+impl Descriptor {
+    fn stack_size(&self) -> usize {
+        self.stack_base as usize - self.stack_limit as usize
+    }
+
+    fn canary_intact(&self) -> bool {
+        (0..STACK_CANARY_WORDS).all(|offset| unsafe {
+            *self.stack_limit.offset(offset) == STACK_CANARY
+        })
+    }
+
+    fn stack_used(&self) -> usize {
+        let mut ptr = unsafe { self.stack_limit.offset(STACK_CANARY_WORDS) };
+        unsafe {
+            while ptr < self.stack_base && *ptr == STACK_FILL {
+                ptr = ptr.offset(1)
+            }
+        }
+        self.stack_base as usize - ptr as usize
+    }
+}
+
 #[derive(Debug)]
 struct Thread {
     generator:   Generator<WaitResult, WaitRequest, OwnedStack>,
@@ -56,23 +251,45 @@ struct Thread {
 }
 
 impl Thread {
-    unsafe fn new<F>(io: &Io, stack_size: usize, f: F) -> ThreadHandle
+    unsafe fn new<F>(io: &Io, name: &'static str, stack_size: usize, f: F) -> ThreadHandle
             where F: 'static + FnOnce(Io) + Send {
         let spawned = io.spawned.clone();
+        let threads = io.threads.clone();
         let sockets = io.sockets.clone();
+        let network = io.network.clone();
 
         let stack = OwnedStack::new(stack_size);
-        ThreadHandle::new(Thread {
+        let descriptor = Descriptor {
+            name:        name,
+            spawned_at:  clock::get_ms(),
+            priority:    Cell::new(Priority::Normal),
+            stack_limit: stack.limit() as *const u32,
+            stack_base:  stack.base() as *const u32,
+            stack_peak:  Cell::new(0)
+        };
+        let mut ptr = descriptor.stack_limit as *mut u32;
+        while ptr < descriptor.stack_base as *mut u32 {
+            *ptr = STACK_FILL;
+            ptr = ptr.offset(1)
+        }
+        for offset in 0..STACK_CANARY_WORDS {
+            *(descriptor.stack_limit as *mut u32).offset(offset) = STACK_CANARY
+        }
+
+        ThreadHandle::new(descriptor, Thread {
             generator: Generator::unsafe_new(stack, |yielder, _| {
                 f(Io {
                     yielder: Some(yielder),
                     spawned: spawned,
-                    sockets: sockets
+                    threads: threads,
+                    sockets: sockets,
+                    network: network
                 })
             }),
             waiting_for: WaitRequest {
                 event:   None,
-                timeout: None
+                timeout: None,
+                notify:  None
             },
             interrupted: false
         })
@@ -92,42 +309,123 @@ impl Thread {
 }
 
 #[derive(Clone)]
-pub struct ThreadHandle(Urc<RefCell<Thread>>);
+pub struct ThreadHandle {
+    thread:     Urc<RefCell<Thread>>,
+    descriptor: Urc<Descriptor>,
+    // Notified by the scheduler once the thread has terminated.
+    exited:     Notify
+}
 
 impl ThreadHandle {
-    fn new(thread: Thread) -> ThreadHandle {
-        ThreadHandle(Urc::new(RefCell::new(thread)))
+    fn new(descriptor: Descriptor, thread: Thread) -> ThreadHandle {
+        ThreadHandle {
+            thread:     Urc::new(RefCell::new(thread)),
+            descriptor: Urc::new(descriptor),
+            exited:     Notify::new()
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        self.descriptor.name
+    }
+
+    pub fn priority(&self) -> Priority {
+        self.descriptor.priority.get()
+    }
+
+    pub fn set_priority(&self, priority: Priority) {
+        self.descriptor.priority.set(priority)
     }
 
     pub fn terminated(&self) -> bool {
-        match self.0.try_borrow() {
+        match self.thread.try_borrow() {
             Ok(thread) => thread.terminated(),
             Err(_) => false // the running thread hasn't terminated
         }
     }
 
     pub fn interrupt(&self) {
-        match self.0.try_borrow_mut() {
+        match self.thread.try_borrow_mut() {
             Ok(mut thread) => thread.interrupt(),
             Err(_) => panic!("cannot interrupt the running thread")
         }
     }
+
+    pub fn info(&self) -> ThreadInfo {
+        let state = match self.thread.try_borrow() {
+            Err(_) => ThreadState::Running,
+            Ok(ref thread) if thread.terminated() => ThreadState::Terminated,
+            Ok(ref thread) if thread.waiting_for.event.is_some() => ThreadState::WaitingForEvent,
+            Ok(ref thread) if thread.waiting_for.timeout.is_some() => ThreadState::WaitingForTimeout,
+            Ok(_) => ThreadState::Runnable
+        };
+        ThreadInfo {
+            name:       self.descriptor.name,
+            spawned_at: self.descriptor.spawned_at,
+            priority:   self.descriptor.priority.get(),
+            state:      state,
+            stack_size: self.descriptor.stack_size(),
+            stack_used: self.descriptor.stack_used()
+        }
+    }
 }
 
 pub struct Scheduler {
-    threads: Vec<ThreadHandle>,
+    threads: Urc<RefCell<Vec<ThreadHandle>>>,
     spawned: Urc<RefCell<Vec<ThreadHandle>>>,
     sockets: Urc<RefCell<SocketSet>>,
-    run_idx: usize,
+    network: Notify,
+    run_idx: [usize; 2],
+    high_ran_last: bool,
+    // Deadlines of suspended threads, earliest first. The entry of a thread that is
+    // resumed before its deadline is removed, so that it does not end an idle period.
+    timers:  BinaryHeap<Reverse<u64>>,
+    // Set when the last pass over the threads found none of them runnable and none
+    // that have to be polled; nothing can become runnable until a timer expires,
+    // a thread is spawned, or the network is notified.
+    idle:    bool,
+    stack_debug: bool,
 }
 
 impl Scheduler {
     pub fn new() -> Scheduler {
         Scheduler {
-            threads: Vec::new(),
+            threads: Urc::new(RefCell::new(Vec::new())),
             spawned: Urc::new(RefCell::new(Vec::new())),
             sockets: Urc::new(RefCell::new(SocketSet::new(Vec::new()))),
-            run_idx: 0,
+            network: Notify::new(),
+            run_idx: [0; 2],
+            high_ran_last: false,
+            timers:  BinaryHeap::new(),
+            idle:    false,
+            stack_debug: false,
+        }
+    }
+
+    /// When enabled, the stack high-water mark of a thread is measured after
+    /// every context switch, and logged whenever it grows. This is slow.
+    pub fn set_stack_debug(&mut self, enabled: bool) {
+        self.stack_debug = enabled
+    }
+
+    fn check_stack(&self, handle: &ThreadHandle, terminated: bool) {
+        let descriptor = &handle.descriptor;
+        if !descriptor.canary_intact() {
+            panic!("stack overflow in thread {:?} spawned at {} ms ({} bytes of stack)",
+                   descriptor.name, descriptor.spawned_at, descriptor.stack_size())
+        }
+
+        if self.stack_debug {
+            let used = descriptor.stack_used();
+            if used > descriptor.stack_peak.get() {
+                descriptor.stack_peak.set(used);
+                debug!("thread {:?} peak stack usage {}/{} bytes",
+                       descriptor.name, used, descriptor.stack_size())
+            }
+            if terminated {
+                debug!("thread {:?} terminated, peak stack usage {}/{} bytes",
+                       descriptor.name, descriptor.stack_peak.get(), descriptor.stack_size())
+            }
         }
     }
 
@@ -135,56 +433,167 @@ impl Scheduler {
         Io {
             yielder: None,
             spawned: self.spawned.clone(),
-            sockets: self.sockets.clone()
+            threads: self.threads.clone(),
+            sockets: self.sockets.clone(),
+            network: self.network.clone()
         }
     }
 
+    /// Sets how long a thread may run without yielding before a warning is logged,
+    /// and before the runtime panics. The panic threshold is checked when the thread
+    /// yields and, if the gateware has a stall timer, from its interrupt every quarter
+    /// of the threshold, so that the backtrace points at the code that stalled.
+    pub fn set_stall_thresholds(&mut self, warn_ms: Option<u64>, panic_ms: Option<u64>) {
+        stall().warn_after.set(warn_ms);
+        stall().panic_after.set(panic_ms);
+        stall_timer::arm(panic_ms.map(|panic_ms| cmp::max(panic_ms * 250, 1000)))
+    }
+
+    /// Wakes up the threads waiting on a socket. Must be called whenever
+    /// the network interface may have changed the state of any socket.
+    pub fn notify_network(&mut self) {
+        self.network.notify();
+        self.idle = false
+    }
+
     pub fn run(&mut self) {
         self.sockets.borrow_mut().prune();
 
-        self.threads.append(&mut *self.spawned.borrow_mut());
-        if self.threads.len() == 0 { return }
+        if !self.spawned.borrow().is_empty() {
+            self.threads.borrow_mut().append(&mut *self.spawned.borrow_mut());
+            self.idle = false
+        }
+        if self.threads.borrow().len() == 0 { return }
+
+        if unsafe { mem::replace(&mut NOTIFIED, false) } {
+            self.idle = false
+        }
 
         let now = clock::get_ms();
-        let start_idx = self.run_idx;
+        while self.timers.peek().map(|&Reverse(deadline)| now >= deadline).unwrap_or(false) {
+            self.timers.pop();
+            self.idle = false
+        }
+        if self.idle { return }
+
+        // High priority threads get every other turn when they are runnable, so that
+        // they never wait for more than one normal priority thread, yet cannot starve them.
+        let order = if self.high_ran_last {
+            [Priority::Normal, Priority::High]
+        } else {
+            [Priority::High, Priority::Normal]
+        };
+        let mut polled = false;
+        for &priority in order.iter() {
+            if self.run_one(priority, now, &mut polled) {
+                self.high_ran_last = priority == Priority::High;
+                return
+            }
+        }
+        // We've checked every thread and none of them are runnable.
+        self.idle = !polled
+    }
+
+    // Resumes the next runnable thread with the given priority, if any.
+    fn run_one(&mut self, priority: Priority, now: u64, polled: &mut bool) -> bool {
+        let thread_count = self.threads.borrow().len();
+        let start_idx = self.run_idx[priority as usize] % thread_count;
+        let mut run_idx = start_idx;
         loop {
-            self.run_idx = (self.run_idx + 1) % self.threads.len();
-
-            let result = {
-                let &mut Thread { ref mut generator, ref mut interrupted, ref waiting_for } =
-                    &mut *self.threads[self.run_idx].0.borrow_mut();
-                if *interrupted {
-                    *interrupted = false;
-                    generator.resume(WaitResult::Interrupted)
-                } else if waiting_for.event.is_none() && waiting_for.timeout.is_none() {
-                    generator.resume(WaitResult::Completed)
-                } else if waiting_for.timeout.map(|instant| now >= instant).unwrap_or(false) {
-                    generator.resume(WaitResult::TimedOut)
-                } else if waiting_for.event.map(|event| unsafe { (*event)() }).unwrap_or(false) {
-                    generator.resume(WaitResult::Completed)
-                } else if self.run_idx == start_idx {
-                    // We've checked every thread and none of them are runnable.
-                    break
-                } else {
-                    continue
-                }
+            run_idx = (run_idx + 1) % thread_count;
+
+            // The thread list must not stay borrowed while the thread runs,
+            // since the thread may inspect it.
+            let handle = self.threads.borrow()[run_idx].clone();
+            if handle.priority() != priority {
+                if run_idx == start_idx { return false } else { continue }
+            }
+
+            let (result, cancelled) = {
+                let &mut Thread { ref mut generator, ref mut interrupted, ref mut waiting_for } =
+                    &mut *handle.thread.borrow_mut();
+                let notified = match waiting_for.notify {
+                    None => {
+                        *polled |= waiting_for.event.is_some();
+                        true
+                    }
+                    Some((ref notify, ref mut generation)) => {
+                        let notified = notify.generation() != *generation;
+                        *generation = notify.generation();
+                        notified
+                    }
+                };
+
+                let wait_result =
+                    if *interrupted {
+                        *interrupted = false;
+                        WaitResult::Interrupted
+                    } else if waiting_for.event.is_none() && waiting_for.timeout.is_none() {
+                        WaitResult::Completed
+                    } else if waiting_for.timeout.map(|instant| now >= instant).unwrap_or(false) {
+                        WaitResult::TimedOut
+                    } else if notified &&
+                            waiting_for.event.map(|event| unsafe { (*event)() }).unwrap_or(false) {
+                        WaitResult::Completed
+                    } else if run_idx == start_idx {
+                        return false
+                    } else {
+                        continue
+                    };
+
+                let cancelled = match wait_result {
+                    WaitResult::TimedOut => None,
+                    _ => waiting_for.timeout
+                };
+
+                stall().resume(handle.name());
+                profiler::set_thread(Some(handle.name()));
+                let result = generator.resume(wait_result);
+                profiler::set_thread(None);
+                (result, cancelled)
             };
+            if let Some(deadline) = cancelled {
+                self.cancel_timer(deadline)
+            }
+
+            stall().check_warn();
+            if result.is_none() {
+                // A thread that terminates never yields, so check it here.
+                stall().check_panic()
+            }
+            stall().suspend();
+            self.check_stack(&handle, result.is_none());
 
             match result {
                 None => {
                     // The thread has terminated.
-                    self.threads.remove(self.run_idx);
-                    self.run_idx = 0
+                    handle.exited.notify();
+                    self.threads.borrow_mut().remove(run_idx);
+                    self.run_idx[priority as usize] = 0
                 },
                 Some(wait_request) => {
                     // The thread has suspended itself.
-                    let mut thread = self.threads[self.run_idx].0.borrow_mut();
-                    thread.waiting_for = wait_request
+                    if let Some(deadline) = wait_request.timeout {
+                        self.timers.push(Reverse(deadline))
+                    }
+                    let mut thread = handle.thread.borrow_mut();
+                    thread.waiting_for = wait_request;
+                    self.run_idx[priority as usize] = run_idx
                 }
             }
 
-            break
+            return true
+        }
+    }
+
+    // Removes one entry with the given deadline. Several threads may share a deadline,
+    // so each of them owns one entry.
+    fn cancel_timer(&mut self, deadline: u64) {
+        let mut timers = mem::replace(&mut self.timers, BinaryHeap::new()).into_vec();
+        if let Some(index) = timers.iter().position(|&Reverse(timer)| timer == deadline) {
+            timers.swap_remove(index);
         }
+        self.timers = BinaryHeap::from(timers)
     }
 
     pub fn sockets(&self) -> &RefCell<SocketSet> {
@@ -196,17 +605,28 @@ impl Scheduler {
 pub struct Io<'a> {
     yielder: Option<&'a Yielder<WaitResult, WaitRequest, OwnedStack>>,
     spawned: Urc<RefCell<Vec<ThreadHandle>>>,
+    threads: Urc<RefCell<Vec<ThreadHandle>>>,
     sockets: Urc<RefCell<SocketSet>>,
+    network: Notify,
 }
 
 impl<'a> Io<'a> {
-    pub fn spawn<F>(&self, stack_size: usize, f: F) -> ThreadHandle
+    pub fn spawn<F>(&self, name: &'static str, stack_size: usize, f: F) -> ThreadHandle
             where F: 'static + FnOnce(Io) + Send {
-        let handle = unsafe { Thread::new(self, stack_size, f) };
+        let handle = unsafe { Thread::new(self, name, stack_size, f) };
         self.spawned.borrow_mut().push(handle.clone());
         handle
     }
 
+    /// Returns a snapshot of every thread known to the scheduler, including
+    /// the ones that have been spawned but not yet run.
+    pub fn threads(&self) -> Vec<ThreadInfo> {
+        self.threads.borrow().iter()
+            .chain(self.spawned.borrow().iter())
+            .map(|handle| handle.info())
+            .collect()
+    }
+
     fn yielder(&self) -> &'a Yielder<WaitResult, WaitRequest, OwnedStack> {
         self.yielder.expect("cannot suspend the scheduler thread")
     }
@@ -214,18 +634,24 @@ impl<'a> Io<'a> {
     pub fn sleep(&self, duration_ms: u64) -> Result<(), Error> {
         let request = WaitRequest {
             timeout: Some(clock::get_ms() + duration_ms),
-            event:   None
+            event:   None,
+            notify:  None
         };
 
-        match self.yielder().suspend(request) {
+        match self.yield_(request) {
             WaitResult::TimedOut => Ok(()),
             WaitResult::Interrupted => Err(Error::Interrupted),
             _ => unreachable!()
         }
     }
 
+    fn yield_(&self, request: WaitRequest) -> WaitResult {
+        stall().check_panic();
+        self.yielder().suspend(request)
+    }
+
     fn suspend(&self, request: WaitRequest) -> Result<(), Error> {
-        match self.yielder().suspend(request) {
+        match self.yield_(request) {
             WaitResult::Completed => Ok(()),
             WaitResult::TimedOut => Err(Error::TimedOut),
             WaitResult::Interrupted => Err(Error::Interrupted)
@@ -235,23 +661,51 @@ impl<'a> Io<'a> {
     pub fn relinquish(&self) -> Result<(), Error> {
         self.suspend(WaitRequest {
             timeout: None,
-            event:   None
+            event:   None,
+            notify:  None
         })
     }
 
+    /// Suspends the thread until `f` returns true. `f` is checked on every
+    /// pass of the scheduler; prefer `until_notified` where possible.
     pub fn until<F: FnMut() -> bool>(&self, mut f: F) -> Result<(), Error> {
         let f = unsafe { mem::transmute::<&mut FnMut() -> bool, *mut FnMut() -> bool>(&mut f) };
         self.suspend(WaitRequest {
             timeout: None,
-            event:   Some(f)
+            event:   Some(f),
+            notify:  None
+        })
+    }
+
+    /// Suspends the thread until `f` returns true. `f` is only checked after
+    /// `notify` has been notified.
+    pub fn until_notified<F: FnMut() -> bool>(&self, notify: &Notify, f: F)
+                                              -> Result<(), Error> {
+        self.until_notified_for(notify, None, f)
+    }
+
+    /// Like `until_notified`, but fails with `Error::TimedOut` if `f` has not
+    /// returned true within `timeout_ms` milliseconds.
+    pub fn until_notified_for<F: FnMut() -> bool>(&self, notify: &Notify,
+                                                  timeout_ms: Option<u64>, mut f: F)
+                                                  -> Result<(), Error> {
+        if f() { return Ok(()) }
+
+        let f = unsafe { mem::transmute::<&mut FnMut() -> bool, *mut FnMut() -> bool>(&mut f) };
+        self.suspend(WaitRequest {
+            timeout: timeout_ms.map(|timeout_ms| clock::get_ms() + timeout_ms),
+            event:   Some(f),
+            notify:  Some((notify.clone(), notify.generation()))
         })
     }
 
-    pub fn until_ok<T, E, F>(&self, mut f: F) -> Result<T, Error>
+    /// Suspends the thread until `f` returns `Ok`, and returns its value. `f` is
+    /// only checked after `notify` has been notified.
+    pub fn until_ok<T, E, F>(&self, notify: &Notify, mut f: F) -> Result<T, Error>
         where F: FnMut() -> result::Result<T, E>
     {
         let mut value = None;
-        self.until(|| {
+        self.until_notified(notify, || {
             if let Ok(result) = f() {
                 value = Some(result)
             }
@@ -261,37 +715,41 @@ impl<'a> Io<'a> {
     }
 
     pub fn join(&self, handle: ThreadHandle) -> Result<(), Error> {
-        self.until(move || handle.terminated())
+        let exited = handle.exited.clone();
+        self.until_notified(&exited, move || handle.terminated())
     }
 }
 
 #[derive(Clone)]
-pub struct Mutex(Urc<Cell<bool>>);
+pub struct Mutex(Urc<Cell<bool>>, Notify);
 
 impl Mutex {
     pub fn new() -> Mutex {
-        Mutex(Urc::new(Cell::new(false)))
+        Mutex(Urc::new(Cell::new(false)), Notify::new())
     }
 
     pub fn lock<'a>(&'a self, io: &Io) -> Result<MutexGuard<'a>, Error> {
-        io.until(|| !self.0.get())?;
+        io.until_notified(&self.1, || !self.0.get())?;
         self.0.set(true);
-        Ok(MutexGuard(&*self.0))
+        Ok(MutexGuard(&*self.0, &self.1))
     }
 }
 
-pub struct MutexGuard<'a>(&'a Cell<bool>);
+pub struct MutexGuard<'a>(&'a Cell<bool>, &'a Notify);
 
 impl<'a> Drop for MutexGuard<'a> {
     fn drop(&mut self) {
-        self.0.set(false)
+        self.0.set(false);
+        self.1.notify()
     }
 }
 
+// Socket state only changes when the network interface is polled, so threads
+// waiting on a socket are only woken up by `Scheduler::notify_network`.
 macro_rules! until {
     ($socket:expr, $ty:ty, |$var:ident| $cond:expr) => ({
         let (sockets, handle) = ($socket.io.sockets.clone(), $socket.handle);
-        $socket.io.until(move || {
+        $socket.io.until_notified(&$socket.io.network, move || {
             let mut sockets = sockets.borrow_mut();
             let $var = sockets.get::<$ty>(handle);
             $cond
@@ -365,7 +823,7 @@ impl<'a> TcpListener<'a> {
         // This handles the case where a remote socket immediately sends a FIN--
         // that still counts as accepting even though nothing may be sent.
         let (sockets, handle) = (self.io.sockets.clone(), self.handle.get());
-        self.io.until(move || {
+        self.io.until_notified(&self.io.network, move || {
             let mut sockets = sockets.borrow_mut();
             let socket = sockets.get::<TcpSocketLower>(handle);
             socket.may_send() || socket.may_recv()
@@ -465,6 +923,18 @@ impl<'a> TcpStream<'a> {
         self.with_lower(|mut s| s.set_keep_alive(value.map(Duration::from_millis)))
     }
 
+    /// Waits until data can be received or the peer has closed its half of the
+    /// connection; fails with `Error::TimedOut` if neither happens within `timeout_ms`
+    /// milliseconds.
+    pub fn wait_recv(&self, timeout_ms: Option<u64>) -> Result<(), Error> {
+        let (sockets, handle) = (self.io.sockets.clone(), self.handle);
+        self.io.until_notified_for(&self.io.network, timeout_ms, move || {
+            let mut sockets = sockets.borrow_mut();
+            let socket = sockets.get::<TcpSocketLower>(handle);
+            socket.can_recv() || !socket.may_recv()
+        })
+    }
+
     pub fn close(&self) -> Result<(), Error> {
         self.with_lower(|mut s| s.close());
         until!(self, TcpSocketLower, |s| !s.is_open())?;
@@ -473,6 +943,12 @@ impl<'a> TcpStream<'a> {
         // then the last ACK will never be sent.
         self.io.relinquish()
     }
+
+    pub fn abort(&self) -> Result<(), Error> {
+        self.with_lower(|mut s| s.abort());
+        // give the socket a chance to send the RST before it is released.
+        self.io.relinquish()
+    }
 }
 
 impl<'a> Read for TcpStream<'a> {
@@ -543,3 +1019,78 @@ impl<'a> Drop for TcpStream<'a> {
         self.io.sockets.borrow_mut().release(self.handle)
     }
 }
+
+type UdpSocketBuffer = ::smoltcp::socket::UdpSocketBuffer<'static, 'static>;
+type UdpSocketLower  = ::smoltcp::socket::UdpSocket<'static, 'static>;
+type UdpPacketMetadata = ::smoltcp::socket::UdpPacketMetadata;
+
+pub struct UdpSocket<'a> {
+    io:     &'a Io<'a>,
+    handle: SocketHandle
+}
+
+impl<'a> UdpSocket<'a> {
+    pub fn new(io: &'a Io<'a>, buffer_depth: usize, buffer_size: usize) -> UdpSocket<'a> {
+        let rx_buffer = UdpSocketBuffer::new(vec![UdpPacketMetadata::EMPTY; buffer_depth],
+                                             vec![0; buffer_size]);
+        let tx_buffer = UdpSocketBuffer::new(vec![UdpPacketMetadata::EMPTY; buffer_depth],
+                                             vec![0; buffer_size]);
+        let handle = io.sockets
+            .borrow_mut()
+            .add(UdpSocketLower::new(rx_buffer, tx_buffer));
+        UdpSocket {
+            io:     io,
+            handle: handle
+        }
+    }
+
+    fn with_lower<F, R>(&self, f: F) -> R
+            where F: FnOnce(SocketRef<UdpSocketLower>) -> R {
+        let mut sockets = self.io.sockets.borrow_mut();
+        let result = f(sockets.get(self.handle));
+        result
+    }
+
+    pub fn bind<T: Into<IpEndpoint>>(&self, endpoint: T) -> Result<(), Error> {
+        let endpoint = endpoint.into();
+        self.with_lower(|mut s| s.bind(endpoint))
+            .map_err(|err| err.into())
+    }
+
+    pub fn can_send(&self) -> bool {
+        self.with_lower(|s| s.can_send())
+    }
+
+    pub fn can_recv(&self) -> bool {
+        self.with_lower(|s| s.can_recv())
+    }
+
+    pub fn send_to(&self, buf: &[u8], endpoint: IpEndpoint) -> Result<(), Error> {
+        until!(self, UdpSocketLower, |s| s.can_send())?;
+        self.with_lower(|mut s| s.send_slice(buf, endpoint))
+            .map_err(|err| err.into())
+    }
+
+    /// Waits until a datagram can be received; fails with `Error::TimedOut` if none
+    /// arrives within `timeout_ms` milliseconds.
+    pub fn wait_recv(&self, timeout_ms: Option<u64>) -> Result<(), Error> {
+        let (sockets, handle) = (self.io.sockets.clone(), self.handle);
+        self.io.until_notified_for(&self.io.network, timeout_ms, move || {
+            let mut sockets = sockets.borrow_mut();
+            sockets.get::<UdpSocketLower>(handle).can_recv()
+        })
+    }
+
+    pub fn recv_from(&self, buf: &mut [u8]) -> Result<(usize, IpEndpoint), Error> {
+        until!(self, UdpSocketLower, |s| s.can_recv())?;
+        self.with_lower(|mut s| s.recv_slice(buf))
+            .map_err(|err| err.into())
+    }
+}
+
+impl<'a> Drop for UdpSocket<'a> {
+    fn drop(&mut self) {
+        self.with_lower(|mut s| s.close());
+        self.io.sockets.borrow_mut().release(self.handle)
+    }
+}
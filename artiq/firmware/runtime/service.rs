@@ -0,0 +1,116 @@
+use core::cell::Cell;
+use board_misoc::config;
+use sched::{TcpStream, Error as SchedError};
+use urc::Urc;
+
+// Without window scaling, smoltcp cannot advertise a larger receive window.
+const MAX_BUFFER_SIZE: usize = 65535;
+
+/// Network parameters of a TCP service that serves several clients and waits for
+/// their requests.
+///
+/// Each parameter can be overridden with the `<service>_buffer_size`,
+/// `<service>_max_clients` and `<service>_idle_timeout` (in milliseconds,
+/// `0` to disable) config keys.
+#[derive(Debug, Clone, Copy)]
+pub struct ServiceConfig {
+    pub buffer_size:  usize,
+    pub max_clients:  usize,
+    pub idle_timeout: Option<u64>,
+}
+
+fn read_key<T, F>(service: &str, key: &str, validate: F) -> Option<T>
+        where T: ::core::str::FromStr, F: FnOnce(&T) -> bool {
+    let key = format!("{}_{}", service, key);
+    match config::read_str(&key, |r| r.map(|s| s.parse::<T>())) {
+        Ok(Ok(value)) => {
+            if validate(&value) {
+                Some(value)
+            } else {
+                error!("out of range `{}` config key, using default", key);
+                None
+            }
+        }
+        Ok(Err(_)) => {
+            error!("invalid `{}` config key, using default", key);
+            None
+        }
+        Err(_) => None
+    }
+}
+
+/// Reads the `<service>_buffer_size` config key, which is the only one that applies
+/// to services handling a single connection at a time.
+pub fn read_buffer_size(service: &str, default: usize) -> usize {
+    read_key(service, "buffer_size", |&size: &usize| size > 0 && size <= MAX_BUFFER_SIZE)
+        .unwrap_or(default)
+}
+
+impl ServiceConfig {
+    pub fn read(service: &str, default: ServiceConfig) -> ServiceConfig {
+        let buffer_size = read_buffer_size(service, default.buffer_size);
+        let max_clients =
+            read_key(service, "max_clients", |&count: &usize| count > 0)
+                .unwrap_or(default.max_clients);
+        let idle_timeout =
+            read_key(service, "idle_timeout", |_: &u64| true)
+                .map(|timeout| if timeout == 0 { None } else { Some(timeout) })
+                .unwrap_or(default.idle_timeout);
+        ServiceConfig {
+            buffer_size:  buffer_size,
+            max_clients:  max_clients,
+            idle_timeout: idle_timeout
+        }
+    }
+}
+
+/// Waits for the next request of a client, aborting the connection when nothing
+/// has been received from it for `idle_timeout` milliseconds.
+///
+/// Returns `false` if the connection was aborted.
+pub fn wait_request(stream: &TcpStream, idle_timeout: Option<u64>) -> Result<bool, SchedError> {
+    match stream.wait_recv(idle_timeout) {
+        Ok(()) => Ok(true),
+        Err(SchedError::TimedOut) => {
+            info!("closing idle connection from {}", stream.remote_endpoint());
+            stream.abort()?;
+            Ok(false)
+        }
+        Err(err) => Err(err)
+    }
+}
+
+/// Counts the clients of a service that spawns a thread per connection.
+#[derive(Clone)]
+pub struct ClientCount {
+    count: Urc<Cell<usize>>,
+    max:   usize
+}
+
+impl ClientCount {
+    pub fn new(max: usize) -> ClientCount {
+        ClientCount {
+            count: Urc::new(Cell::new(0)),
+            max:   max
+        }
+    }
+
+    /// Reserves a slot for a new client, or returns `None` if the service is full.
+    /// The slot is released when the returned value is dropped.
+    pub fn acquire(&self) -> Option<ClientSlot> {
+        if self.count.get() >= self.max {
+            None
+        } else {
+            self.count.set(self.count.get() + 1);
+            Some(ClientSlot(self.count.clone()))
+        }
+    }
+}
+
+pub struct ClientSlot(Urc<Cell<usize>>);
+
+impl Drop for ClientSlot {
+    fn drop(&mut self) {
+        self.0.set(self.0.get() - 1)
+    }
+}
@@ -3,14 +3,19 @@ use alloc::{Vec, String};
 use byteorder::{ByteOrder, NetworkEndian};
 
 use io::{Read, Write, Error as IoError};
-use board_misoc::{ident, cache, config};
+use board_misoc::{ident, cache, clock, config};
 use {mailbox, rpc_queue, kernel};
 use urc::Urc;
-use sched::{ThreadHandle, Io, Mutex, TcpListener, TcpStream, Error as SchedError};
+use sched::{ThreadHandle, Priority, Io, Mutex, TcpListener, TcpStream, Error as SchedError};
 use rtio_clocking;
+use rtio_mgt;
 use rtio_dma::Manager as DmaManager;
 use cache::Cache;
+use kernel_cache::{self, KernelCache};
+use inflate;
+use service;
 use kern_hwreq;
+use perf;
 use watchdog::WatchdogSet;
 use board_artiq::drtio_routing;
 
@@ -56,6 +61,8 @@ impl From<IoError<SchedError>> for Error<SchedError> {
     }
 }
 
+const DEFAULT_KERNEL_CACHE_SIZE: usize = 512 * 1024;
+
 macro_rules! unexpected {
      ($($arg:tt)*) => (return Err(Error::Unexpected(format!($($arg)*))));
 }
@@ -64,14 +71,26 @@ macro_rules! unexpected {
 #[derive(Debug)]
 struct Congress {
     cache: Cache,
+    kernel_cache: KernelCache,
     dma_manager: DmaManager,
     finished_cleanly: Cell<bool>
 }
 
 impl Congress {
     fn new() -> Congress {
+        let kernel_cache_size =
+            match config::read_str("kernel_cache_size", |r| r.map(|s| s.parse::<usize>())) {
+                Ok(Ok(size)) => size,
+                Ok(Err(_)) => {
+                    error!("invalid `kernel_cache_size` config key, using default");
+                    DEFAULT_KERNEL_CACHE_SIZE
+                }
+                Err(_) => DEFAULT_KERNEL_CACHE_SIZE
+            };
+
         Congress {
             cache: Cache::new(),
+            kernel_cache: KernelCache::new(kernel_cache_size),
             dma_manager: DmaManager::new(),
             finished_cleanly: Cell::new(true)
         }
@@ -79,29 +98,68 @@ impl Congress {
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
-enum KernelState {
+pub enum KernelState {
     Absent,
     Loaded,
     Running,
     RpcWait
 }
 
+// Snapshot of the session state for monitoring
+#[derive(Debug, Clone, Copy)]
+pub struct Status {
+    pub host_connected: bool,
+    pub kernel_state: KernelState
+}
+
+static mut STATUS: Status = Status {
+    host_connected: false,
+    kernel_state: KernelState::Absent
+};
+
+pub fn status() -> Status {
+    unsafe { STATUS }
+}
+
 // Per-connection state
 #[derive(Debug)]
 struct Session<'a> {
     congress: &'a mut Congress,
+    host_connected: bool,
     kernel_state: KernelState,
     watchdog_set: WatchdogSet,
-    log_buffer: String
+    log_buffer: String,
+    forward_log: bool,
+    run_id: u32,
+    staged_kernel: Option<Vec<u8>>,
+    run_stats: host::RunStats,
+    run_started_at: u64,
+    last_run_stats: Option<host::RunStats>
 }
 
 impl<'a> Session<'a> {
-    fn new(congress: &mut Congress) -> Session {
+    fn new(congress: &mut Congress, host_connected: bool) -> Session {
         Session {
             congress: congress,
+            host_connected: host_connected,
             kernel_state: KernelState::Absent,
             watchdog_set: WatchdogSet::new(),
-            log_buffer: String::new()
+            log_buffer: String::new(),
+            forward_log: false,
+            run_id: 0,
+            staged_kernel: None,
+            run_stats: host::RunStats::default(),
+            run_started_at: 0,
+            last_run_stats: None
+        }
+    }
+
+    fn publish_status(&self) {
+        unsafe {
+            STATUS = Status {
+                host_connected: self.host_connected,
+                kernel_state: self.kernel_state
+            }
         }
     }
 
@@ -112,19 +170,39 @@ impl<'a> Session<'a> {
         }
     }
 
-    fn flush_log_buffer(&mut self) {
+    fn finish_run_stats(&mut self) {
+        self.run_stats.run_us = clock::get_us() - self.run_started_at;
+        self.last_run_stats = Some(self.run_stats)
+    }
+
+    fn flush_log_buffer(&mut self, stream: Option<&mut TcpStream>)
+                       -> Result<(), IoError<SchedError>> {
         if &self.log_buffer[self.log_buffer.len() - 1..] == "\n" {
+            let mut stream = if self.forward_log { stream } else { None };
             for line in self.log_buffer.lines() {
                 info!(target: "kernel", "{}", line);
+                if let Some(ref mut stream) = stream {
+                    host_write(stream, host::Reply::KernelLog {
+                        run_id:  self.run_id,
+                        message: line
+                    })?
+                }
             }
             self.log_buffer.clear()
         }
+        Ok(())
     }
 }
 
 impl<'a> Drop for Session<'a> {
     fn drop(&mut self) {
+        if self.running() {
+            perf::kernel_finished()
+        }
         unsafe { kernel::stop() }
+        self.host_connected = false;
+        self.kernel_state = KernelState::Absent;
+        self.publish_status()
     }
 }
 
@@ -134,6 +212,12 @@ fn host_read<R>(reader: &mut R) -> Result<host::Request, Error<R::ReadError>>
     let request = host::Request::read_from(reader)?;
     match &request {
         &host::Request::LoadKernel(_) => debug!("comm<-host LoadLibrary(...)"),
+        &host::Request::LoadAndCacheKernel { .. } =>
+            debug!("comm<-host LoadAndCacheKernel(...)"),
+        &host::Request::StageKernel(_) => debug!("comm<-host StageKernel(...)"),
+        &host::Request::LoadCompressedKernel { size, ref kernel, .. } =>
+            debug!("comm<-host LoadCompressedKernel({} bytes, {} compressed)",
+                   size, kernel.len()),
         _ => debug!("comm<-host {:?}", request)
     }
     Ok(request)
@@ -207,6 +291,7 @@ unsafe fn kern_load(io: &Io, session: &mut Session, library: &[u8])
         unexpected!("attempted to load a new kernel while a kernel was running")
     }
 
+    let started_at = clock::get_us();
     kernel::start();
 
     kern_send(io, &kern::LoadRequest(&library))?;
@@ -214,6 +299,10 @@ unsafe fn kern_load(io: &Io, session: &mut Session, library: &[u8])
         match reply {
             kern::LoadReply(Ok(())) => {
                 session.kernel_state = KernelState::Loaded;
+                session.run_stats = host::RunStats {
+                    load_us: clock::get_us() - started_at,
+                    ..host::RunStats::default()
+                };
                 Ok(())
             }
             kern::LoadReply(Err(error)) => {
@@ -226,16 +315,157 @@ unsafe fn kern_load(io: &Io, session: &mut Session, library: &[u8])
     })
 }
 
+fn capabilities(congress: &Congress) -> host::Capabilities {
+    #[cfg(has_i2c)]
+    let i2c_buses = ::board_misoc::csr::CONFIG_I2C_BUS_COUNT as u8;
+    #[cfg(not(has_i2c))]
+    let i2c_buses = 0;
+
+    host::Capabilities {
+        rtio_dma:      cfg!(has_rtio_dma),
+        drtio:         cfg!(has_drtio),
+        rtio_analyzer: cfg!(has_rtio_analyzer),
+        moninj:        cfg!(any(has_rtio_moninj, has_drtio)),
+        grabber:       cfg!(has_grabber),
+        kernel_cache:  congress.kernel_cache.enabled(),
+        kernel_deflate: true,
+        kernel_abort:  true,
+        kernel_staging: true,
+        run_stats:     true,
+        kernel_log:    true,
+        converter_spi: cfg!(has_converter_spi),
+        i2c_buses:     i2c_buses,
+        drtio_links:   rtio_mgt::drtio::link_count() as u8,
+    }
+}
+
 fn kern_run(session: &mut Session) -> Result<(), Error<SchedError>> {
     if session.kernel_state != KernelState::Loaded {
         unexpected!("attempted to run a kernel while not in Loaded state")
     }
 
     session.kernel_state = KernelState::Running;
+    session.run_id = session.run_id.wrapping_add(1);
+    session.run_started_at = clock::get_us();
+    perf::kernel_started();
     // TODO: make this a separate request
     kern_acknowledge()
 }
 
+fn kern_abort(session: &mut Session) {
+    if session.running() {
+        perf::kernel_finished();
+        session.finish_run_stats()
+    }
+    unsafe {
+        kernel::stop();
+        session.congress.cache.unborrow()
+    }
+    session.kernel_state = KernelState::Absent;
+    session.watchdog_set = WatchdogSet::new();
+    session.log_buffer.clear();
+    session.staged_kernel = None
+}
+
+fn cache_uploaded_kernel(session: &mut Session, hash: kernel_cache::Hash, kernel: Vec<u8>) {
+    if let Err(()) = session.congress.kernel_cache.insert_uploaded(hash, kernel) {
+        warn!("not caching kernel: its SHA-256 hash does not match the one sent by the host")
+    }
+}
+
+fn host_load_reply(stream: &mut TcpStream, result: Result<(), Error<SchedError>>)
+                  -> Result<(), Error<SchedError>> {
+    match result {
+        Ok(()) => host_write(stream, host::Reply::LoadCompleted)?,
+        Err(error) => {
+            let mut description = String::new();
+            write!(&mut description, "{}", error).unwrap();
+            host_write(stream, host::Reply::LoadFailed(&description))?;
+            kern_acknowledge()?;
+        }
+    }
+    Ok(())
+}
+
+// Counts the bytes read from or written to a stream.
+struct Counted<'a, T: 'a + ?Sized> {
+    inner: &'a mut T,
+    count: usize
+}
+
+impl<'a, T: 'a + ?Sized> Counted<'a, T> {
+    fn new(inner: &'a mut T) -> Counted<'a, T> {
+        Counted { inner: inner, count: 0 }
+    }
+}
+
+impl<'a, T: Read + ?Sized> Read for Counted<'a, T> {
+    type ReadError = T::ReadError;
+
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::ReadError> {
+        let length = self.inner.read(buf)?;
+        self.count += length;
+        Ok(length)
+    }
+}
+
+impl<'a, T: Write + ?Sized> Write for Counted<'a, T> {
+    type WriteError = T::WriteError;
+    type FlushError = T::FlushError;
+
+    fn write(&mut self, buf: &[u8]) -> Result<usize, Self::WriteError> {
+        let length = self.inner.write(buf)?;
+        self.count += length;
+        Ok(length)
+    }
+
+    fn flush(&mut self) -> Result<(), Self::FlushError> {
+        self.inner.flush()
+    }
+
+    fn size_hint(&mut self, min: usize, max: Option<usize>) {
+        self.inner.size_hint(min, max)
+    }
+}
+
+fn host_load_staged(io: &Io, stream: &mut TcpStream, session: &mut Session,
+                    kernel: Vec<u8>) -> Result<(), Error<SchedError>> {
+    if session.kernel_state == KernelState::Loaded {
+        // Replaces an earlier staged kernel that was not run.
+        unsafe { kernel::stop() }
+        session.kernel_state = KernelState::Absent;
+    }
+
+    let result = unsafe { kern_load(io, session, &kernel) };
+    match result {
+        Ok(()) => {
+            let load_us = session.run_stats.load_us;
+            debug!("staged kernel loaded in {} us", load_us);
+            host_write(stream, host::Reply::StagedKernelLoaded { load_us: load_us })?;
+            Ok(())
+        }
+        Err(error) => host_load_reply(stream, Err(error))
+    }
+}
+
+// Rejects sizes that could never be loaded before allocating the output, since
+// the size comes straight from the host.
+fn host_inflate(compressed: &[u8], size: usize) -> Result<Vec<u8>, String> {
+    let image_size = kern::KERNELCPU_LAST_ADDRESS - kern::KERNELCPU_PAYLOAD_ADDRESS + 1;
+    if size > image_size {
+        return Err(format!("kernel of {} bytes does not fit into the {} byte image area",
+                           size, image_size))
+    }
+    // Leave room for the allocator rounding the block up.
+    let largest_idle = unsafe { ::ALLOC.stats() }.largest_idle;
+    if size + 64 > largest_idle {
+        return Err(format!("kernel of {} bytes does not fit into the {} byte largest free \
+                            heap block", size, largest_idle))
+    }
+    inflate::inflate(compressed, size)
+        .map_err(|error| format!("cannot decompress kernel: {}", error))
+}
+
 fn process_host_message(io: &Io,
                         stream: &mut TcpStream,
                         session: &mut Session) -> Result<(), Error<SchedError>> {
@@ -243,26 +473,68 @@ fn process_host_message(io: &Io,
         host::Request::SystemInfo => {
             host_write(stream, host::Reply::SystemInfo {
                 ident: ident::read(&mut [0; 64]),
-                finished_cleanly: session.congress.finished_cleanly.get()
+                finished_cleanly: session.congress.finished_cleanly.get(),
+                capabilities: capabilities(session.congress)
             })?;
             session.congress.finished_cleanly.set(true)
         }
 
-        host::Request::LoadKernel(kernel) =>
-            match unsafe { kern_load(io, session, &kernel) } {
-                Ok(()) => host_write(stream, host::Reply::LoadCompleted)?,
-                Err(error) => {
-                    let mut description = String::new();
-                    write!(&mut description, "{}", error).unwrap();
-                    host_write(stream, host::Reply::LoadFailed(&description))?;
-                    kern_acknowledge()?;
+        host::Request::LoadKernel(kernel) => {
+            let result = unsafe { kern_load(io, session, &kernel) };
+            host_load_reply(stream, result)?
+        }
+        host::Request::LoadKernelByHash(hash) =>
+            match session.congress.kernel_cache.take(&hash) {
+                None => host_write(stream, host::Reply::KernelNotCached)?,
+                Some(kernel) => {
+                    let result = unsafe { kern_load(io, session, &kernel) };
+                    session.congress.kernel_cache.insert(hash, kernel);
+                    host_load_reply(stream, result)?
                 }
             },
+        host::Request::LoadAndCacheKernel { hash, kernel } => {
+            let result = unsafe { kern_load(io, session, &kernel) };
+            if result.is_ok() {
+                cache_uploaded_kernel(session, hash, kernel)
+            }
+            host_load_reply(stream, result)?
+        }
+        host::Request::LoadCompressedKernel { hash, size, kernel: compressed } =>
+            match host_inflate(&compressed, size as usize) {
+                Ok(kernel) => {
+                    drop(compressed);
+                    let result = unsafe { kern_load(io, session, &kernel) };
+                    if result.is_ok() {
+                        cache_uploaded_kernel(session, hash, kernel)
+                    }
+                    host_load_reply(stream, result)?
+                }
+                Err(description) =>
+                    // The kernel CPU was never started, so there is nothing to acknowledge.
+                    host_write(stream, host::Reply::LoadFailed(&description))?
+            },
+        host::Request::StageKernel(kernel) =>
+            if session.running() {
+                // Loaded as soon as the running kernel finishes.
+                session.staged_kernel = Some(kernel)
+            } else {
+                host_load_staged(io, stream, session, kernel)?
+            },
         host::Request::RunKernel =>
             match kern_run(session) {
                 Ok(()) => (),
                 Err(_) => host_write(stream, host::Reply::KernelStartupFailed)?
             },
+        host::Request::ForwardKernelLog(enabled) =>
+            session.forward_log = enabled,
+        host::Request::GetRunStats =>
+            host_write(stream, host::Reply::RunStats(session.last_run_stats))?,
+        host::Request::AbortKernel => {
+            // Also acknowledged when no kernel is running, since the host cannot
+            // tell whether the kernel finished before the request arrived.
+            kern_abort(session);
+            host_write(stream, host::Reply::KernelAborted)?
+        }
 
         host::Request::RpcReply { tag } => {
             if session.kernel_state != KernelState::RpcWait {
@@ -276,7 +548,8 @@ fn process_host_message(io: &Io,
                         "expected root value slot from kernel CPU, not {:?}", other)
                 }
             })?;
-            rpc::recv_return(stream, &tag, slot, &|size| -> Result<_, Error<SchedError>> {
+            let mut reader = Counted::new(&mut *stream);
+            rpc::recv_return(&mut reader, &tag, slot, &|size| -> Result<_, Error<SchedError>> {
                 if size == 0 {
                     // Don't try to allocate zero-length values, as RpcRecvReply(0) is
                     // used to terminate the kernel-side receive loop.
@@ -292,6 +565,7 @@ fn process_host_message(io: &Io,
                 })?)
             })?;
             kern_send(io, &kern::RpcRecvReply(Ok(0)))?;
+            session.run_stats.rpc_bytes_received += reader.count as u64;
 
             session.kernel_state = KernelState::Running
         }
@@ -360,13 +634,13 @@ fn process_kern_message(io: &Io, aux_mutex: &Mutex,
                 session.log_buffer
                        .write_fmt(args)
                        .unwrap_or_else(|_| warn!("cannot append to session log buffer"));
-                session.flush_log_buffer();
+                session.flush_log_buffer(stream.as_mut().map(|stream| &mut **stream))?;
                 kern_acknowledge()
             }
 
             &kern::LogSlice(arg) => {
                 session.log_buffer += arg;
-                session.flush_log_buffer();
+                session.flush_log_buffer(stream.as_mut().map(|stream| &mut **stream))?;
                 kern_acknowledge()
             }
 
@@ -380,6 +654,7 @@ fn process_kern_message(io: &Io, aux_mutex: &Mutex,
             }
             &kern::DmaRecordStop { duration } => {
                 session.congress.dma_manager.record_stop(duration);
+                session.run_stats.dma_recorded += 1;
                 cache::flush_l2_cache();
                 kern_acknowledge()
             }
@@ -388,6 +663,7 @@ fn process_kern_message(io: &Io, aux_mutex: &Mutex,
                 kern_acknowledge()
             }
             &kern::DmaRetrieveRequest { name } => {
+                session.run_stats.dma_played += 1;
                 session.congress.dma_manager.with_trace(name, |trace, duration| {
                     kern_send(io, &kern::DmaRetrieveReply {
                         trace:    trace,
@@ -410,8 +686,13 @@ fn process_kern_message(io: &Io, aux_mutex: &Mutex,
                     None => unexpected!("unexpected RPC in flash kernel"),
                     Some(ref mut stream) => {
                         host_write(stream, host::Reply::RpcRequest { async: async })?;
-                        rpc::send_args(stream, service, tag, data)?;
-                        if !async {
+                        let mut writer = Counted::new(&mut **stream);
+                        rpc::send_args(&mut writer, service, tag, data)?;
+                        session.run_stats.rpc_bytes_sent += writer.count as u64;
+                        if async {
+                            session.run_stats.async_rpcs += 1
+                        } else {
+                            session.run_stats.sync_rpcs += 1;
                             session.kernel_state = KernelState::RpcWait
                         }
                         kern_acknowledge()
@@ -426,6 +707,7 @@ fn process_kern_message(io: &Io, aux_mutex: &Mutex,
             },
 
             &kern::CacheGetRequest { key } => {
+                session.run_stats.cache_gets += 1;
                 let value = session.congress.cache.get(key);
                 kern_send(io, &kern::CacheGetReply {
                     // Zing! This transmute is only safe because we dynamically track
@@ -435,11 +717,14 @@ fn process_kern_message(io: &Io, aux_mutex: &Mutex,
             }
 
             &kern::CachePutRequest { key, value } => {
+                session.run_stats.cache_puts += 1;
                 let succeeded = session.congress.cache.put(key, value).is_ok();
                 kern_send(io, &kern::CachePutReply { succeeded: succeeded })
             }
 
             &kern::RunFinished => {
+                perf::kernel_finished();
+                session.finish_run_stats();
                 unsafe { kernel::stop() }
                 session.kernel_state = KernelState::Absent;
                 unsafe { session.congress.cache.unborrow() }
@@ -454,6 +739,8 @@ fn process_kern_message(io: &Io, aux_mutex: &Mutex,
                 exception: kern::Exception { name, message, param, file, line, column, function },
                 backtrace
             } => {
+                perf::kernel_finished();
+                session.finish_run_stats();
                 unsafe { kernel::stop() }
                 session.kernel_state = KernelState::Absent;
                 unsafe { session.congress.cache.unborrow() }
@@ -486,13 +773,15 @@ fn process_kern_message(io: &Io, aux_mutex: &Mutex,
 }
 
 fn process_kern_queued_rpc(stream: &mut TcpStream,
-                           _session: &mut Session) -> Result<(), Error<SchedError>> {
+                           session: &mut Session) -> Result<(), Error<SchedError>> {
     rpc_queue::dequeue(|slice| {
         debug!("comm<-kern (async RPC)");
         let length = NetworkEndian::read_u32(slice) as usize;
         host_write(stream, host::Reply::RpcRequest { async: true })?;
         debug!("{:?}", &slice[4..][..length]);
         stream.write_all(&slice[4..][..length])?;
+        session.run_stats.async_rpcs += 1;
+        session.run_stats.rpc_bytes_sent += length as u64;
         Ok(())
     })
 }
@@ -502,7 +791,7 @@ fn host_kernel_worker(io: &Io, aux_mutex: &Mutex,
                       up_destinations: &Urc<RefCell<[bool; drtio_routing::DEST_COUNT]>>,
                       stream: &mut TcpStream,
                       congress: &mut Congress) -> Result<(), Error<SchedError>> {
-    let mut session = Session::new(congress);
+    let mut session = Session::new(congress, true);
 
     loop {
         if stream.can_recv() {
@@ -511,6 +800,10 @@ fn host_kernel_worker(io: &Io, aux_mutex: &Mutex,
             return Ok(())
         }
 
+        let queued = rpc_queue::len() as u32;
+        if queued > session.run_stats.rpc_queue_peak {
+            session.run_stats.rpc_queue_peak = queued
+        }
         while !rpc_queue::empty() {
             process_kern_queued_rpc(stream, &mut session)?
         }
@@ -521,6 +814,12 @@ fn host_kernel_worker(io: &Io, aux_mutex: &Mutex,
                 Some(stream), &mut session)?;
         }
 
+        if !session.running() {
+            if let Some(kernel) = session.staged_kernel.take() {
+                host_load_staged(io, stream, &mut session, kernel)?
+            }
+        }
+
         if session.kernel_state == KernelState::Running {
             if let Some(idx) = session.watchdog_set.expired() {
                 host_write(stream, host::Reply::WatchdogExpired)?;
@@ -533,6 +832,7 @@ fn host_kernel_worker(io: &Io, aux_mutex: &Mutex,
             }
         }
 
+        session.publish_status();
         io.relinquish()?
     }
 }
@@ -542,7 +842,7 @@ fn flash_kernel_worker(io: &Io, aux_mutex: &Mutex,
                        up_destinations: &Urc<RefCell<[bool; drtio_routing::DEST_COUNT]>>,
                        congress: &mut Congress,
                        config_key: &str) -> Result<(), Error<SchedError>> {
-    let mut session = Session::new(congress);
+    let mut session = Session::new(congress, false);
 
     config::read(config_key, |result| {
         match result {
@@ -575,11 +875,12 @@ fn flash_kernel_worker(io: &Io, aux_mutex: &Mutex,
             return Err(Error::ClockFailure)
         }
 
+        session.publish_status();
         io.relinquish()?
     }
 }
 
-fn respawn<F>(io: &Io, handle: &mut Option<ThreadHandle>, f: F)
+fn respawn<F>(io: &Io, handle: &mut Option<ThreadHandle>, name: &'static str, f: F)
         where F: 'static + FnOnce(Io) + Send {
     match handle.take() {
         None => (),
@@ -591,13 +892,19 @@ fn respawn<F>(io: &Io, handle: &mut Option<ThreadHandle>, f: F)
         }
     }
 
-    *handle = Some(io.spawn(16384, f))
+    let thread = io.spawn(name, 16384, f);
+    thread.set_priority(Priority::High);
+    *handle = Some(thread)
 }
 
 pub fn thread(io: Io, aux_mutex: &Mutex,
         routing_table: &Urc<RefCell<drtio_routing::RoutingTable>>,
         up_destinations: &Urc<RefCell<[bool; drtio_routing::DEST_COUNT]>>) {
-    let listener = TcpListener::new(&io, 65535);
+    // A new connection always replaces the current one, and a host may stay silent
+    // for as long as its kernel runs, so only the buffer size is configurable.
+    let buffer_size = service::read_buffer_size("session", 65535);
+
+    let listener = TcpListener::new(&io, buffer_size);
     listener.listen(1381).expect("session: cannot listen");
     info!("accepting network sessions");
 
@@ -609,7 +916,7 @@ pub fn thread(io: Io, aux_mutex: &Mutex,
         let routing_table = routing_table.clone();
         let up_destinations = up_destinations.clone();
         let congress = congress.clone();
-        respawn(&io, &mut kernel_thread, move |io| {
+        respawn(&io, &mut kernel_thread, "startup kernel", move |io| {
             let routing_table = routing_table.borrow();
             let mut congress = congress.borrow_mut();
             info!("running startup kernel");
@@ -647,7 +954,7 @@ pub fn thread(io: Io, aux_mutex: &Mutex,
             let up_destinations = up_destinations.clone();
             let congress = congress.clone();
             let stream = stream.into_handle();
-            respawn(&io, &mut kernel_thread, move |io| {
+            respawn(&io, &mut kernel_thread, "host kernel", move |io| {
                 let routing_table = routing_table.borrow();
                 let mut congress = congress.borrow_mut();
                 let mut stream = TcpStream::from_handle(&io, stream);
@@ -673,7 +980,7 @@ pub fn thread(io: Io, aux_mutex: &Mutex,
             let routing_table = routing_table.clone();
             let up_destinations = up_destinations.clone();
             let congress = congress.clone();
-            respawn(&io, &mut kernel_thread, move |io| {
+            respawn(&io, &mut kernel_thread, "idle kernel", move |io| {
                 let routing_table = routing_table.borrow();
                 let mut congress = congress.borrow_mut();
                 match flash_kernel_worker(&io, &aux_mutex, &routing_table, &up_destinations, &mut *congress, "idle_kernel") {
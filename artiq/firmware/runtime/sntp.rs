@@ -0,0 +1,128 @@
+use byteorder::{ByteOrder, NetworkEndian};
+use smoltcp::wire::{IpAddress, IpEndpoint};
+
+use board_misoc::{config, clock};
+use sched::{Io, UdpSocket, Error as SchedError};
+
+const NTP_PORT:   u16 = 123;
+const LOCAL_PORT: u16 = 1123;
+
+// Seconds between the NTP epoch (1900-01-01) and the UNIX epoch (1970-01-01).
+const NTP_UNIX_OFFSET: u64 = 2_208_988_800;
+
+const TIMEOUT_MS:          u64 = 2_000;
+const RETRY_INTERVAL_MS:   u64 = 10_000;
+const DEFAULT_INTERVAL_MS: u64 = 64_000;
+
+#[derive(Fail, Debug)]
+enum Error {
+    #[fail(display = "no reply")]
+    NoReply,
+    #[fail(display = "server is not synchronized")]
+    Unsynchronized,
+    #[fail(display = "invalid timestamp")]
+    InvalidTimestamp,
+    #[fail(display = "{}", _0)]
+    Sched(#[cause] SchedError)
+}
+
+impl From<SchedError> for Error {
+    fn from(value: SchedError) -> Error {
+        Error::Sched(value)
+    }
+}
+
+fn ntp_to_unix_us(timestamp: u64) -> Result<u64, Error> {
+    let seconds  = (timestamp >> 32).checked_sub(NTP_UNIX_OFFSET)
+                                    .ok_or(Error::InvalidTimestamp)?;
+    let fraction = timestamp & 0xffff_ffff;
+    Ok(seconds * 1_000_000 + ((fraction * 1_000_000) >> 32))
+}
+
+/// Returns the offset between the UNIX epoch and the local clock, and the round-trip delay,
+/// both in microseconds.
+fn query(socket: &UdpSocket, server: IpEndpoint) -> Result<(u64, u64), Error> {
+    let mut packet = [0; 48];
+    packet[0] = (4 << 3) | 3; // version 4, client mode
+    // The server echoes the transmit timestamp back as the originate timestamp,
+    // so any unique value identifies the reply; use the local clock.
+    let t1 = clock::get_us();
+    NetworkEndian::write_u64(&mut packet[40..48], t1);
+    socket.send_to(&packet, server)?;
+
+    let deadline = clock::get_ms() + TIMEOUT_MS;
+    loop {
+        let remaining_ms = deadline.saturating_sub(clock::get_ms());
+        match socket.wait_recv(Some(remaining_ms)) {
+            Ok(()) => (),
+            Err(SchedError::TimedOut) => return Err(Error::NoReply),
+            Err(err) => return Err(err.into())
+        }
+
+        let (length, endpoint) = socket.recv_from(&mut packet)?;
+        let t4 = clock::get_us();
+        if endpoint != server || length < 48 ||
+                NetworkEndian::read_u64(&packet[24..32]) != t1 {
+            continue
+        }
+
+        let leap    = packet[0] >> 6;
+        let mode    = packet[0] & 7;
+        let stratum = packet[1];
+        if leap == 3 || mode != 4 || stratum == 0 {
+            return Err(Error::Unsynchronized)
+        }
+
+        let t2 = ntp_to_unix_us(NetworkEndian::read_u64(&packet[32..40]))?;
+        let t3 = ntp_to_unix_us(NetworkEndian::read_u64(&packet[40..48]))?;
+        // Assume the network delay is symmetric.
+        let delay = (t4 - t1).saturating_sub(t3.saturating_sub(t2));
+        let utc_at_t4 = t3 + delay / 2;
+        return Ok((utc_at_t4 - t4, delay))
+    }
+}
+
+fn thread(io: Io, server: IpEndpoint, interval_ms: u64) {
+    let socket = UdpSocket::new(&io, 4, 256);
+    socket.bind(LOCAL_PORT).expect("sntp: cannot bind");
+
+    loop {
+        let sleep_ms = match query(&socket, server) {
+            Ok((offset, delay)) => {
+                match clock::utc_offset_us() {
+                    None =>
+                        info!("synchronized to {} (round-trip delay {}us)", server, delay),
+                    Some(previous) =>
+                        debug!("adjusted by {}us (round-trip delay {}us)",
+                               offset as i64 - previous as i64, delay)
+                }
+                clock::set_utc_offset_us(Some(offset));
+                interval_ms
+            }
+            Err(err) => {
+                warn!("cannot synchronize to {}: {}", server, err);
+                RETRY_INTERVAL_MS
+            }
+        };
+        io.sleep(sleep_ms).unwrap();
+    }
+}
+
+pub fn startup(io: &Io) {
+    let server = match config::read_str("sntp_server", |r| r.map(|s| s.parse::<IpAddress>())) {
+        Ok(Ok(addr)) => IpEndpoint::new(addr, NTP_PORT),
+        Ok(Err(_)) => {
+            error!("invalid `sntp_server` config key");
+            return
+        }
+        Err(_) => return
+    };
+    let interval_ms = match config::read_str("sntp_interval", |r| r.map(|s| s.parse::<u64>())) {
+        Ok(Ok(interval_s)) => interval_s * 1000,
+        _ => DEFAULT_INTERVAL_MS
+    };
+
+    info!("synchronizing wall-clock time to SNTP server {} every {}s",
+          server, interval_ms / 1000);
+    io.spawn("sntp", 4096, move |io| thread(io, server, interval_ms));
+}
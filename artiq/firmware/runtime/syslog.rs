@@ -0,0 +1,91 @@
+use alloc::{Vec, String};
+use log::LevelFilter;
+use smoltcp::wire::{IpAddress, IpEndpoint};
+
+use board_misoc::config;
+use logger_artiq::BufferLogger;
+use sched::{Io, UdpSocket, Notify};
+
+const SYSLOG_PORT: u16 = 514;
+const LOCAL_PORT:  u16 = 1514;
+
+const QUEUE_SIZE: usize = 1 << 13;
+
+static mut QUEUE: [u8; QUEUE_SIZE] = [0; QUEUE_SIZE];
+
+// Notified by the logger whenever a record has been queued.
+static mut QUEUE_NOTIFY: Option<Notify> = None;
+
+fn notify_queue() {
+    if let Some(notify) = unsafe { QUEUE_NOTIFY.as_ref() } {
+        notify.notify()
+    }
+}
+
+fn server_endpoint() -> Option<IpEndpoint> {
+    config::read_str("syslog_server", |r| {
+        match r {
+            Ok(s) => {
+                if let Ok(endpoint) = s.parse::<IpEndpoint>() {
+                    Some(endpoint)
+                } else if let Ok(addr) = s.parse::<IpAddress>() {
+                    Some(IpEndpoint::new(addr, SYSLOG_PORT))
+                } else {
+                    error!("invalid syslog server address {:?}", s);
+                    None
+                }
+            }
+            Err(_) => None
+        }
+    })
+}
+
+fn thread(io: Io, server: IpEndpoint) {
+    let socket = UdpSocket::new(&io, 16, QUEUE_SIZE);
+    socket.bind(LOCAL_PORT).expect("syslog: cannot bind");
+
+    let notify = unsafe { QUEUE_NOTIFY.clone().unwrap() };
+    loop {
+        io.until_notified(&notify, || BufferLogger::with(|logger|
+            logger.syslog().map(|queue| !queue.is_empty()).unwrap_or(false))).unwrap();
+
+        // Copy the records out so that logging can proceed while we wait for
+        // space in the socket buffer.
+        let mut records = Vec::new();
+        BufferLogger::with(|logger| {
+            if let Ok(mut queue) = logger.syslog() {
+                for record in queue.records() {
+                    records.push(Vec::from(record))
+                }
+                queue.clear()
+            }
+        });
+
+        for record in records {
+            // Any error here would only be reported through the log again.
+            let _ = socket.send_to(&record, server);
+        }
+    }
+}
+
+pub fn startup(io: &Io, protocol_addr: IpAddress) {
+    let server = match server_endpoint() {
+        Some(server) => server,
+        None => return
+    };
+
+    let hostname = config::read_str("hostname", |r| r.map(String::from))
+        .unwrap_or_else(|_| format!("{}", protocol_addr));
+    let max_level = match config::read_str("syslog_level", |r| r.map(|s| s.parse())) {
+        Ok(Ok(level_filter)) => level_filter,
+        _ => LevelFilter::Info
+    };
+
+    unsafe { QUEUE_NOTIFY = Some(Notify::new()) }
+    BufferLogger::with(|logger|
+        logger.enable_syslog(unsafe { &mut QUEUE[..] }, &hostname, max_level, notify_queue));
+    info!("forwarding log records at {} and above to syslog server {} as {}",
+          max_level, server, hostname);
+
+    io.spawn("syslog", 4096, move |io| thread(io, server));
+}
@@ -1,12 +1,14 @@
 #!/usr/bin/env python3
 
 import argparse
+import logging
+import sys
 import struct
 
 from artiq.tools import add_common_args, init_logger
 from artiq.master.databases import DeviceDB
 from artiq.coredevice.comm_kernel import CommKernel
-from artiq.coredevice.comm_mgmt import CommMgmt
+from artiq.coredevice.comm_mgmt import CommMgmt, PerfEvent
 from artiq.coredevice.profiler import CallgrindWriter
 
 
@@ -106,6 +108,19 @@ def get_argparser():
     p_stop = subparsers.add_parser("stop",
                                    help="stop profiling")
 
+    p_stream = subparsers.add_parser("stream",
+                                     help="profile continuously and print the "
+                                          "share of samples taken in each "
+                                          "thread over every window")
+    p_stream.add_argument("--interval", metavar="MICROS", type=int, default=2000,
+                          help="sampling interval, in microseconds")
+    p_stream.add_argument("--window", metavar="MILLIS", type=int, default=10000,
+                          help="window length, in milliseconds")
+    p_stream.add_argument("--hits-size", metavar="ENTRIES", type=int, default=4096,
+                          help="hit buffer size")
+    p_stream.add_argument("--edges-size", metavar="ENTRIES", type=int, default=0,
+                          help="edge buffer size")
+
     p_save = subparsers.add_parser("save",
                                    help="save profile")
     p_save.add_argument("output", metavar="OUTPUT", type=argparse.FileType("w"),
@@ -118,6 +133,29 @@ def get_argparser():
     p_save.add_argument("--no-demangle",
                         dest="demangle", default=True, action="store_false",
                         help="disable symbol demangling")
+    p_save.add_argument("--thread", metavar="NAME", type=str, default=None,
+                        help="only save the samples taken in the threads "
+                             "with this name")
+
+    # performance counters
+    t_perf = tools.add_parser("perf",
+                              help="use the performance counters of the "
+                                   "communications CPU")
+
+    subparsers = t_perf.add_subparsers(dest="action")
+    subparsers.required = True
+
+    p_configure = subparsers.add_parser("configure",
+                                        help="configure and reset counters")
+    p_configure.add_argument("--kernel-only", default=False, action="store_true",
+                             help="only count while a kernel is running")
+    p_configure.add_argument("counters", metavar="EVENTS", nargs="*",
+                             help="comma-separated events counted by each "
+                                  "counter, among: " +
+                                  ", ".join(PerfEvent.__members__))
+
+    p_read = subparsers.add_parser("read",
+                                   help="read counters")
 
     # misc debug
     t_debug = tools.add_parser("debug",
@@ -127,7 +165,13 @@ def get_argparser():
     subparsers.required = True
 
     p_allocator = subparsers.add_parser("allocator",
-                                        help="show heap layout")
+                                        help="show heap statistics")
+
+    p_check_heap = subparsers.add_parser("check-heap",
+                                         help="check heap integrity")
+
+    p_threads = subparsers.add_parser("threads",
+                                      help="list runtime threads")
 
     return parser
 
@@ -182,19 +226,82 @@ def main():
             mgmt.start_profiler(args.interval, args.hits_size, args.edges_size)
         elif args.action == "stop":
             mgmt.stop_profiler()
+        elif args.action == "stream":
+            for hits, edges, metadata in mgmt.stream_profile(
+                    args.interval, args.window, args.hits_size, args.edges_size):
+                samples = metadata["samples"]
+                shares = []
+                for thread, thread_hits in sorted(hits.items()):
+                    share = 100 * sum(thread_hits.values()) / max(samples, 1)
+                    shares.append("{} {:.1f}%".format(thread, share))
+                print("{:.3f} s: {} samples, {} dropped; {}".format(
+                    metadata["duration"] / 1e6, samples, metadata["dropped"],
+                    ", ".join(shares)))
         elif args.action == "save":
-            hits, edges = mgmt.get_profile()
+            hits, edges, metadata = mgmt.get_profile_per_thread()
+            if metadata["dropped"]:
+                logging.warning("%d of %d samples were dropped because the "
+                                "profile storage was full",
+                                metadata["dropped"], metadata["samples"])
             writer = CallgrindWriter(args.output, args.firmware, "or1k-linux",
                                      args.compression, args.demangle)
-            writer.header()
-            for addr, count in hits.items():
-                writer.hit(addr, count)
-            for (caller, callee), count in edges.items():
-                writer.edge(caller, callee, count)
+            if args.thread is not None:
+                if args.thread not in hits:
+                    logging.warning("no samples were taken in thread %s",
+                                    args.thread)
+                threads = [args.thread]
+            else:
+                threads = list(hits.keys())
+            writer.header([
+                ("Firmware", metadata["ident"]),
+                ("Threads", ", ".join(threads)),
+                ("Interval", "{} us".format(metadata["interval"])),
+                ("Duration", "{} us".format(metadata["duration"])),
+                ("Samples", metadata["samples"]),
+                ("Dropped samples", metadata["dropped"]),
+            ])
+            for thread in threads:
+                for addr, count in hits.get(thread, {}).items():
+                    writer.hit(addr, count)
+                for (caller, callee), count in edges.get(thread, {}).items():
+                    writer.edge(caller, callee, count)
+
+    if args.tool == "perf":
+        if args.action == "configure":
+            counters = []
+            for events in args.counters:
+                flags = PerfEvent(0)
+                for event in events.split(","):
+                    flags |= PerfEvent[event.upper()]
+                counters.append(flags)
+            mgmt.configure_perf_counters(counters, args.kernel_only)
+        if args.action == "read":
+            kernel_only, counters = mgmt.read_perf_counters()
+            if kernel_only:
+                print("counted during the last kernel run")
+            for index, (events, value) in enumerate(counters):
+                names = [event.name for event in PerfEvent if event & events]
+                print("{}: {} ({})".format(index, value, ",".join(names)))
 
     if args.tool == "debug":
         if args.action == "allocator":
-            mgmt.debug_allocator()
+            for key, value in mgmt.debug_allocator().items():
+                print("{}: {}".format(key, value))
+        if args.action == "check-heap":
+            corruption = mgmt.check_heap()
+            if corruption is None:
+                print("no heap corruption detected")
+            else:
+                print("heap corruption detected: {}".format(corruption))
+                sys.exit(1)
+        if args.action == "threads":
+            print("{:<16} {:<8} {:<20} {:>12} {:>12}".format(
+                "NAME", "PRIORITY", "STATE", "SPAWNED (ms)", "STACK"))
+            for thread in mgmt.get_threads():
+                print("{:<16} {:<8} {:<20} {:>12} {:>5}/{:<6}".format(
+                    thread["name"], thread["priority"].name,
+                    thread["state"].name, thread["spawned_at"],
+                    thread["stack_used"], thread["stack_size"]))
 
 
 if __name__ == "__main__":
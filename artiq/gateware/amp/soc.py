@@ -28,12 +28,22 @@ class AMPSoC:
                                self.mem_map["mailbox"] | 0x80000000,
                                4*mailbox_size)
 
-    def register_kernel_cpu_csrdevice(self, name, csrs=None):
+    def register_kernel_cpu_csrdevice(self, name, csrs=None, shared=False):
+        """Map a CSR device on the kernel CPU bus. With ``shared``, the
+        comms CPU can also access it, at the same address."""
         if csrs is None:
             csrs = getattr(self, name).get_csrs()
         bank = wishbone.CSRBank(csrs)
         self.submodules += bank
-        self.kernel_cpu.add_wb_slave(self.mem_map[name], 4*2**bank.decode_bits, bank.bus)
+        length = 4*2**bank.decode_bits
+        if shared:
+            kernel_bus = wishbone.Interface()
+            comms_bus = wishbone.Interface()
+            self.submodules += wishbone.Arbiter([kernel_bus, comms_bus], bank.bus)
+            self.kernel_cpu.add_wb_slave(self.mem_map[name], length, kernel_bus)
+            self.add_wb_slave(self.mem_map[name], length, comms_bus)
+        else:
+            self.kernel_cpu.add_wb_slave(self.mem_map[name], length, bank.bus)
         self.add_csr_region(name,
                             self.mem_map[name] | 0x80000000, 32,
                             csrs)
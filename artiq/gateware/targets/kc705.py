@@ -129,6 +129,9 @@ class _StandaloneBase(MiniSoC, AMPSoC):
                          **kwargs)
         AMPSoC.__init__(self)
         add_identifier(self)
+        # Marvell 88E1111, strapped to PHY address 7
+        self.config["HAS_ETHPHY_MDIO"] = None
+        self.config["ETHPHY_MDIO_ADDR"] = 7
 
         if isinstance(self.platform.toolchain, XilinxVivadoToolchain):
             self.platform.toolchain.bitstream_commands.extend([
@@ -141,6 +144,11 @@ class _StandaloneBase(MiniSoC, AMPSoC):
         self.csr_devices.append("timer1")
         self.interrupt_devices.append("timer1")
 
+        # Checks for runtime threads that do not yield; timer1 belongs to the profiler.
+        self.submodules.timer2 = timer.Timer()
+        self.csr_devices.append("timer2")
+        self.interrupt_devices.append("timer2")
+
         self.submodules.leds = gpio.GPIOOut(Cat(
             self.platform.request("user_led", 0),
             self.platform.request("user_led", 1)))
@@ -167,7 +175,7 @@ class _StandaloneBase(MiniSoC, AMPSoC):
         self.submodules.rtio = rtio.KernelInitiator(self.rtio_tsc)
         self.submodules.rtio_dma = ClockDomainsRenamer("sys_kernel")(
             rtio.DMA(self.get_native_sdram_if()))
-        self.register_kernel_cpu_csrdevice("rtio")
+        self.register_kernel_cpu_csrdevice("rtio", shared=True)
         self.register_kernel_cpu_csrdevice("rtio_dma")
         self.submodules.cri_con = rtio.CRIInterconnectShared(
             [self.rtio.cri, self.rtio_dma.cri],
@@ -384,7 +392,7 @@ class SMA_SPI(_StandaloneBase):
         self.submodules.rtio = rtio.KernelInitiator(self.rtio_tsc)
         self.submodules.rtio_dma = ClockDomainsRenamer("sys_kernel")(
             rtio.DMA(self.get_native_sdram_if()))
-        self.register_kernel_cpu_csrdevice("rtio")
+        self.register_kernel_cpu_csrdevice("rtio", shared=True)
         self.register_kernel_cpu_csrdevice("rtio_dma")
         self.submodules.cri_con = rtio.CRIInterconnectShared(
             [self.rtio.cri, self.rtio_dma.cri],
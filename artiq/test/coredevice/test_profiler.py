@@ -0,0 +1,39 @@
+import time
+
+from artiq.coredevice.comm_mgmt import CommMgmt
+from artiq.test.hardware_testbench import ExperimentCase
+
+
+class ProfilerTest(ExperimentCase):
+    def setUp(self):
+        super().setUp()
+        core_addr = self.device_mgr.get_desc("core")["arguments"]["host"]
+        self.mgmt = CommMgmt(core_addr)
+
+    def tearDown(self):
+        self.mgmt.stop_profiler()
+        self.mgmt.close()
+        super().tearDown()
+
+    def profile(self, hits_size, edges_size):
+        try:
+            self.mgmt.start_profiler(1000, hits_size, edges_size)
+        except IOError:
+            self.skipTest("profiler not available on this core device")
+        time.sleep(0.5)
+        return self.mgmt.get_profile()
+
+    def test_hits_only(self):
+        # Without edge storage, every sample records the interrupted PC
+        # and nothing has to be unwound.
+        hits, edges, metadata = self.profile(1000, 0)
+        self.assertGreater(metadata["samples"], 0)
+        self.assertEqual(metadata["dropped"], 0)
+        self.assertGreater(len(hits), 0)
+        self.assertEqual(edges, {})
+
+    def test_edges(self):
+        hits, edges, metadata = self.profile(1000, 1000)
+        self.assertGreater(metadata["samples"], 0)
+        self.assertGreater(len(hits), 0)
+        self.assertGreater(len(edges), 0)
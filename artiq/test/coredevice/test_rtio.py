@@ -1,7 +1,7 @@
 # Copyright (C) 2014, 2015 M-Labs Limited
 # Copyright (C) 2014, 2015 Robert Jordens <jordens@gmail.com>
 
-import os, unittest
+import os, unittest, threading, logging
 import numpy as np
 
 from math import sqrt
@@ -9,7 +9,7 @@ from math import sqrt
 from artiq.experiment import *
 from artiq.test.hardware_testbench import ExperimentCase
 from artiq.coredevice import exceptions
-from artiq.coredevice.comm_mgmt import CommMgmt
+from artiq.coredevice.comm_mgmt import CommMgmt, ThreadPriority
 from artiq.coredevice.comm_analyzer import (StoppedMessage, OutputMessage, InputMessage,
                                             decode_dump, get_analyzer_dump)
 
@@ -181,6 +181,55 @@ class Watchdog(EnvExperiment):
                 pass
 
 
+class AbortKernel(EnvExperiment):
+    def build(self):
+        self.setattr_device("core")
+
+    def abort(self):
+        self.core.comm.abort()
+
+    @kernel
+    def run(self):
+        self.abort()
+        while True:
+            pass
+
+
+class StageKernel(EnvExperiment):
+    def build(self, abort=False):
+        self.setattr_device("core")
+        self.abort = abort
+
+    def stage(self):
+        self.core.stage(StageKernel.staged, (self,), {})
+        if self.abort:
+            self.core.comm.abort()
+
+    @kernel
+    def staged(self):
+        pass
+
+    @kernel
+    def stage_next(self):
+        self.stage()
+        if self.abort:
+            while True:
+                pass
+
+    def run(self):
+        self.stage_next()
+        self.staged()
+
+
+class KernelLog(EnvExperiment):
+    def build(self):
+        self.setattr_device("core")
+
+    @kernel
+    def run(self):
+        core_log("forwarded", 42)
+
+
 class LoopbackCount(EnvExperiment):
     def build(self, npulses):
         self.setattr_device("core")
@@ -527,6 +576,39 @@ class CoredeviceTest(ExperimentCase):
         with self.assertRaises(exceptions.WatchdogExpired):
             self.execute(Watchdog)
 
+    def test_abort_kernel(self):
+        with self.assertRaises(exceptions.KernelAborted):
+            self.execute(AbortKernel)
+        # the connection is kept and usable for the next kernel
+        self.execute(TimeKeepsRunning)
+
+    def test_stage_kernel(self):
+        comm = self.device_mgr.get("core").comm
+        comm.staged_load_time = None
+        self.execute(StageKernel)
+        self.assertIsNotNone(comm.staged_load_time)
+        self.assertGreater(comm.staged_load_time, 0)
+
+    def test_stage_abort_kernel(self):
+        # the staged kernel is dropped with the aborted one
+        with self.assertRaises(exceptions.KernelAborted):
+            self.execute(StageKernel, abort=True)
+        # and staging works again afterwards
+        self.execute(StageKernel)
+
+    def test_forward_kernel_log(self):
+        comm = self.device_mgr.get("core").comm
+        comm.forward_kernel_log = True
+        comm.check_system_info()
+        if comm.capabilities is None or not comm.capabilities["kernel_log"]:
+            self.skipTest("firmware cannot forward kernel logs")
+        with self.assertLogs("kernel", logging.INFO) as logs:
+            self.execute(KernelLog)
+        records = [record for record in logs.records
+                   if "forwarded 42" in record.getMessage()]
+        self.assertEqual(len(records), 1)
+        self.assertTrue(hasattr(records[0], "run_id"))
+
     def test_time_keeps_running(self):
         self.execute(TimeKeepsRunning)
         t1 = self.dataset_mgr.get("time_at_start")
@@ -590,6 +672,45 @@ class RPCTest(ExperimentCase):
         self.assertLess(rpc_time_mean, 3.5*ms)
         self.assertLess(self.dataset_mgr.get("rpc_time_stddev"), 1*ms)
 
+    @unittest.skipUnless(artiq_low_latency,
+                         "timings are dependent on CPU load and network conditions")
+    def test_rpc_timing_under_load(self):
+        self.execute(RPCTiming)
+        idle_mean = self.dataset_mgr.get("rpc_time_mean")
+
+        # Keep the management service busy while the RPCs are made; the session
+        # threads run at high priority, so they must not wait behind it.
+        core_addr = self.device_mgr.get_desc("core")["arguments"]["host"]
+        mgmt = CommMgmt(core_addr)
+        stop = threading.Event()
+        def load():
+            while not stop.is_set():
+                mgmt.get_threads()
+        thread = threading.Thread(target=load)
+        thread.start()
+        try:
+            self.execute(RPCTiming)
+        finally:
+            stop.set()
+            thread.join()
+        threads = mgmt.get_threads()
+        mgmt.close()
+
+        for thread in threads:
+            if thread["name"] in ("session", "host kernel"):
+                self.assertEqual(thread["priority"], ThreadPriority.HIGH)
+        loaded_mean = self.dataset_mgr.get("rpc_time_mean")
+        print(idle_mean, loaded_mean)
+        self.assertLess(loaded_mean, 1.5*idle_mean + 200*us)
+        self.assertLess(self.dataset_mgr.get("rpc_time_stddev"), 1*ms)
+
+    def test_run_stats(self):
+        self.execute(RPCTiming)
+        stats = self.device_mgr.get("core").comm.get_run_stats()
+        self.assertGreaterEqual(stats["sync_rpcs"], 100)
+        self.assertGreater(stats["rpc_bytes_sent"], 0)
+        self.assertGreater(stats["run_time"], 0)
+
 
 class _DMA(EnvExperiment):
     def build(self, trace_name="test_rtio"):